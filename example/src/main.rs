@@ -102,11 +102,12 @@ async fn main() {
         vec![PgDbAgentQueryActionParams::new(pool, query, action)],
         Duration::from_secs(1),
         error_handler,
-    );
+    )
+    .unwrap();
 
     let agent = PgDbIdleAgent::new(params);
 
-    let handle = agent.start().await;
+    let handle = agent.start().await.unwrap();
 
     tokio::time::sleep(Duration::from_secs(5)).await;
 
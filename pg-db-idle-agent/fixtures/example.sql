@@ -0,0 +1,11 @@
+CREATE TABLE example (
+    id SERIAL PRIMARY KEY,
+    data TEXT NOT NULL,
+    is_sent BOOLEAN NOT NULL,
+    version INT NOT NULL
+);
+
+INSERT INTO example (data, is_sent, version) VALUES
+    ('Some random text', false, 0),
+    ('Another text', true, 1),
+    ('third text', true, 0);
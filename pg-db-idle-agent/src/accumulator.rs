@@ -0,0 +1,38 @@
+use std::{
+    any::Any,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+pub(crate) type Fold<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// A value folded over every actioned row, see `PgDbAgentParams::with_accumulator`.
+/// The value's type is erased here so neither the params nor `AgentHandle` need another type parameter.
+pub(crate) struct Accumulator<T> {
+    /// `Mutex<A>`, read back through `AgentHandle::accumulator::<A>`.
+    pub(crate) state: Arc<dyn Any + Send + Sync>,
+    pub(crate) fold: Fold<T>,
+}
+
+impl<T> Accumulator<T> {
+    pub(crate) fn new<A, G>(initial: A, fold: G) -> Self
+    where
+        A: Send + 'static,
+        G: Fn(&mut A, &T) + Send + Sync + 'static,
+    {
+        let state = Arc::new(Mutex::new(initial));
+        let folded = Arc::clone(&state);
+        Self {
+            state,
+            fold: Arc::new(move |row| fold(&mut folded.lock().unwrap_or_else(PoisonError::into_inner), row)),
+        }
+    }
+}
+
+/// Current value of a type-erased accumulator state, `None` if it doesn't hold an `A`.
+pub(crate) fn read<A>(state: &(dyn Any + Send + Sync)) -> Option<A>
+where
+    A: Clone + 'static,
+{
+    let state = state.downcast_ref::<Mutex<A>>()?;
+    Some(state.lock().unwrap_or_else(PoisonError::into_inner).clone())
+}
@@ -0,0 +1,71 @@
+use crate::{ActionError, ActionErrorHandler, RowAction, RowContext};
+
+type ChainedAction<T> = Box<dyn Fn(&T) -> Result<(), ActionError> + Send + Sync>;
+
+/// Action that runs several independent actions on each row in the order they were added, e.g. log it, count
+/// it and forward it, without running the query once per action.
+/// A fallible action that fails is reported to the error handler (or logged without one) and the row still goes
+/// to the actions after it.
+pub struct ActionChain<T> {
+    actions: Vec<ChainedAction<T>>,
+    on_error: Option<ActionErrorHandler<T>>,
+}
+
+impl<T> Default for ActionChain<T> {
+    fn default() -> Self {
+        Self {
+            actions: Vec::new(),
+            on_error: None,
+        }
+    }
+}
+
+impl<T> ActionChain<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then<A>(mut self, action: A) -> Self
+    where
+        A: Fn(&T) + Send + Sync + 'static,
+    {
+        self.actions.push(Box::new(move |row| {
+            action(row);
+            Ok(())
+        }));
+        self
+    }
+
+    pub fn then_fallible<A>(mut self, action: A) -> Self
+    where
+        A: Fn(&T) -> Result<(), ActionError> + Send + Sync + 'static,
+    {
+        self.actions.push(Box::new(action));
+        self
+    }
+
+    /// Called for every row a fallible action returned an error for.
+    pub fn with_error_handler<E>(mut self, on_error: E) -> Self
+    where
+        E: Fn(&T, ActionError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+}
+
+impl<T> RowAction<T> for ActionChain<T>
+where
+    T: 'static,
+{
+    fn call(&self, row: &T, context: &RowContext) {
+        for action in &self.actions {
+            if let Err(e) = action(row) {
+                match &self.on_error {
+                    Some(on_error) => on_error(row, e),
+                    None => log::warn!("Chained action failed on row {} of the tick: {}", context.index, e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{atomic::Ordering, Arc},
+    time::SystemTime,
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use sqlx::{postgres::PgRow, PgPool};
+use tokio::task::{JoinError, JoinHandle};
+
+use crate::{
+    accumulator::Fold,
+    action_timings::ActionHistogram,
+    agent_summary::AgentTotals,
+    audit::{panic_message, Audit},
+    row_buffer::Reservation,
+    write_back::PendingWrites,
+    AuditOutcome, PartitionKey, RowAction, RowContext, RowFinalizer,
+};
+
+/// Rows of one partition key in the order they were fetched, each with its context.
+pub(crate) type Partition<T> = VecDeque<(T, RowContext)>;
+
+/// A row taken off its partition: the row, its context and its id for the audit.
+type PartitionRow<T> = (T, RowContext, Option<String>);
+
+/// Everything needed to run a query's action over its rows, owned so the run can move into its own task.
+pub(crate) struct ActionRun<T, F>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T>,
+{
+    pub(crate) rows: Vec<T>,
+    pub(crate) action: Arc<F>,
+    pub(crate) blocking_action: bool,
+    pub(crate) partition_key: Option<PartitionKey<T>>,
+    pub(crate) write_pool: PgPool,
+    pub(crate) tick: u64,
+    pub(crate) observed_at: SystemTime,
+    pub(crate) totals: Arc<AgentTotals>,
+    pub(crate) max_total_rows: Option<u64>,
+    pub(crate) fold: Option<Fold<T>>,
+    /// Slots of `max_buffered_rows` held for the rows not actioned yet.
+    pub(crate) reservation: Option<Reservation>,
+    /// Where the action's durations go with `time_actions`.
+    pub(crate) timings: Option<Arc<ActionHistogram>>,
+    pub(crate) finalize: Option<RowFinalizer<T>>,
+    pub(crate) write_back: Option<PendingWrites<T>>,
+    pub(crate) audit: Option<Audit<T>>,
+    #[cfg(feature = "governor")]
+    pub(crate) rate_limiter: Option<Arc<governor::DefaultDirectRateLimiter>>,
+    #[cfg(feature = "serde")]
+    pub(crate) debug_sink: Option<crate::DebugSink>,
+    #[cfg(feature = "serde")]
+    pub(crate) query: String,
+}
+
+impl<T, F> ActionRun<T, F>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T>,
+{
+    /// Returns `true` if it stopped early because `max_total_rows` was reached.
+    pub(crate) async fn run<S>(mut self) -> bool
+    where
+        S: Spawner<T, F>,
+    {
+        if self.partition_key.is_some() {
+            return self.run_partitioned::<S>().await;
+        }
+        let total = self.rows.len();
+        let rows = std::mem::take(&mut self.rows);
+        for (index, element) in rows.into_iter().enumerate() {
+            let context = self.context(index, total);
+            #[cfg(feature = "governor")]
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.until_ready().await;
+            }
+            // Taken before the call, a blocking action that panics doesn't hand the row back.
+            let row_id = self.audit.as_ref().and_then(|audit| audit.row_id(&element));
+            let element = if self.blocking_action {
+                match S::call_blocking(Arc::clone(&self.action), element, context, self.timings.clone(), self.finalize.clone()).await {
+                    Ok(element) => element,
+                    Err(e) if e.is_panic() => {
+                        let panic = e.into_panic();
+                        self.audit_panic(row_id, panic_message(&*panic)).await;
+                        std::panic::resume_unwind(panic)
+                    }
+                    // Only happens when the runtime shuts down.
+                    Err(_) => return false,
+                }
+            } else {
+                let call_action = || {
+                    call(&*self.action, &element, &context, self.timings.as_deref(), self.finalize.as_deref())
+                }; // This is how to invoke an action that's a property.
+                match &self.audit {
+                    Some(_) => {
+                        if let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(call_action)) {
+                            self.audit_panic(row_id, panic_message(&*panic)).await;
+                            std::panic::resume_unwind(panic);
+                        }
+                    }
+                    None => call_action(),
+                }
+                element
+            };
+            if let Some(audit) = &self.audit {
+                audit.record(row_id, self.tick, AuditOutcome::Success).await;
+            }
+            if let Some(write_back) = &mut self.write_back {
+                write_back.push(&element).await;
+            }
+            if self.row_done(&element, index) {
+                self.flush_writes().await;
+                return true;
+            }
+            // Gives an aborted run (`OverlapPolicy::Cancel`) a chance to stop before the next row.
+            tokio::task::yield_now().await;
+        }
+        self.flush_writes().await;
+        false
+    }
+
+    /// Splits the rows by partition key and runs the partitions concurrently, each one serially in fetch order. Every
+    /// row goes through the rate limiter, the audit and `max_total_rows` like on the sequential path: once the limit
+    /// is reached or an action panicked no partition starts another row, the rows already running are finished.
+    async fn run_partitioned<S>(mut self) -> bool
+    where
+        S: Spawner<T, F>,
+    {
+        let Some(partition_key) = self.partition_key.take() else {
+            return false;
+        };
+        let total = self.rows.len();
+        let mut partitions: Vec<Partition<T>> = Vec::new();
+        let mut by_key = HashMap::new();
+        for (index, element) in std::mem::take(&mut self.rows).into_iter().enumerate() {
+            let partition = *by_key.entry(partition_key(&element)).or_insert_with(|| {
+                partitions.push(VecDeque::new());
+                partitions.len() - 1
+            });
+            partitions[partition].push_back((element, self.context(index, total)));
+        }
+        let mut running = FuturesUnordered::new();
+        for (partition, rows) in partitions.iter_mut().enumerate() {
+            if let Some(row) = self.next_row(rows).await {
+                running.push(self.call_row::<S>(partition, row));
+            }
+        }
+        let mut limit_reached = false;
+        let mut panic = None;
+        while let Some((partition, index, row_id, result)) = running.next().await {
+            let element = match result {
+                Ok(element) => element,
+                Err(e) if e.is_panic() => {
+                    let e = e.into_panic();
+                    self.audit_panic(row_id, panic_message(&*e)).await;
+                    panic.get_or_insert(e);
+                    continue;
+                }
+                // Only happens when the runtime shuts down.
+                Err(_) => return false,
+            };
+            if let Some(audit) = &self.audit {
+                audit.record(row_id, self.tick, AuditOutcome::Success).await;
+            }
+            if let Some(write_back) = &mut self.write_back {
+                write_back.push(&element).await;
+            }
+            limit_reached |= self.row_done(&element, index);
+            if limit_reached || panic.is_some() {
+                continue;
+            }
+            if let Some(row) = self.next_row(&mut partitions[partition]).await {
+                running.push(self.call_row::<S>(partition, row));
+            }
+        }
+        self.flush_writes().await;
+        if let Some(panic) = panic {
+            std::panic::resume_unwind(panic);
+        }
+        limit_reached
+    }
+
+    /// Takes the next row of a partition once the rate limiter lets it through, with its id for the audit.
+    async fn next_row(&self, partition: &mut Partition<T>) -> Option<PartitionRow<T>> {
+        let (element, context) = partition.pop_front()?;
+        #[cfg(feature = "governor")]
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.until_ready().await;
+        }
+        // Taken before the call, an action that panics doesn't hand the row back.
+        let row_id = self.audit.as_ref().and_then(|audit| audit.row_id(&element));
+        Some((element, context, row_id))
+    }
+
+    /// Calls the action on a row of `partition` on its own, so the rows of other partitions run alongside it.
+    fn call_row<S>(
+        &self,
+        partition: usize,
+        (element, context, row_id): PartitionRow<T>,
+    ) -> impl Future<Output = (usize, usize, Option<String>, Result<T, JoinError>)>
+    where
+        S: Spawner<T, F>,
+    {
+        let index = context.index;
+        let call = S::call_blocking(Arc::clone(&self.action), element, context, self.timings.clone(), self.finalize.clone());
+        async move { (partition, index, row_id, call.await) }
+    }
+
+    /// Writes back the rows of the last, partial batch.
+    async fn flush_writes(&mut self) {
+        if let Some(write_back) = &mut self.write_back {
+            write_back.flush().await;
+        }
+    }
+
+    /// Records the failure of an action that panicked, before the panic is re-raised.
+    async fn audit_panic(&self, row_id: Option<String>, message: String) {
+        if let Some(audit) = &self.audit {
+            audit.record(row_id, self.tick, AuditOutcome::Failure(message)).await;
+        }
+    }
+
+    fn context(&self, index: usize, total: usize) -> RowContext {
+        RowContext {
+            index,
+            total: Some(total),
+            write_pool: self.write_pool.clone(),
+            tick: self.tick,
+            observed_at: self.observed_at,
+        }
+    }
+
+    /// Bookkeeping after the action ran for a row, returns `true` once `max_total_rows` was reached.
+    fn row_done(&mut self, element: &T, index: usize) -> bool {
+        if let Some(reservation) = &mut self.reservation {
+            reservation.row_done();
+        }
+        if let Some(fold) = &self.fold {
+            fold(element);
+        }
+        #[cfg(feature = "serde")]
+        if let Some(debug_sink) = &self.debug_sink {
+            crate::AgentEvent::ActionDone {
+                query: self.query.clone(),
+                index,
+            }
+            .write_to(debug_sink);
+        }
+        #[cfg(not(feature = "serde"))]
+        let _ = index;
+        let rows_processed = self.totals.rows.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_total_rows.is_some_and(|max| rows_processed >= max)
+    }
+}
+
+/// Where a query's actions run: on any worker thread for `start`, or on the current `LocalSet` for `start_local`,
+/// which is what lets actions there be `!Send`.
+pub(crate) trait Spawner<T, F>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T>,
+{
+    /// Runs `run` in its own task, for queries with an `OverlapPolicy`.
+    fn spawn_run(run: ActionRun<T, F>) -> JoinHandle<bool>;
+
+    /// Calls the action of a query with `blocking_action` or a partition key and hands the row back.
+    async fn call_blocking(
+        action: Arc<F>,
+        element: T,
+        context: RowContext,
+        timings: Option<Arc<ActionHistogram>>,
+        finalize: Option<RowFinalizer<T>>,
+    ) -> Result<T, JoinError>;
+}
+
+pub(crate) struct MultiThreaded;
+
+impl<T, F> Spawner<T, F> for MultiThreaded
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T> + Send + Sync,
+{
+    fn spawn_run(run: ActionRun<T, F>) -> JoinHandle<bool> {
+        tokio::task::spawn(run.run::<Self>())
+    }
+
+    async fn call_blocking(
+        action: Arc<F>,
+        element: T,
+        context: RowContext,
+        timings: Option<Arc<ActionHistogram>>,
+        finalize: Option<RowFinalizer<T>>,
+    ) -> Result<T, JoinError> {
+        tokio::task::spawn_blocking(move || {
+            call(&*action, &element, &context, timings.as_deref(), finalize.as_deref());
+            element
+        })
+        .await
+    }
+}
+
+pub(crate) struct Local;
+
+impl<T, F> Spawner<T, F> for Local
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T>,
+{
+    fn spawn_run(run: ActionRun<T, F>) -> JoinHandle<bool> {
+        tokio::task::spawn_local(run.run::<Self>())
+    }
+
+    /// A `!Send` action can't move to the blocking pool, so it runs in place.
+    async fn call_blocking(
+        action: Arc<F>,
+        element: T,
+        context: RowContext,
+        timings: Option<Arc<ActionHistogram>>,
+        finalize: Option<RowFinalizer<T>>,
+    ) -> Result<T, JoinError> {
+        call(&*action, &element, &context, timings.as_deref(), finalize.as_deref());
+        Ok(element)
+    }
+}
+
+/// Calls `action` for a row, timed if `timings` is set, then `finalize` whether the action returned or panicked.
+fn call<T, F>(
+    action: &F,
+    element: &T,
+    context: &RowContext,
+    timings: Option<&ActionHistogram>,
+    finalize: Option<&(dyn Fn(&T) + Send + Sync)>,
+) where
+    F: RowAction<T>,
+{
+    let call_action = || match timings {
+        Some(timings) => timings.time(|| action.call(element, context)),
+        None => action.call(element, context),
+    };
+    let Some(finalize) = finalize else {
+        return call_action();
+    };
+    let result = std::panic::catch_unwind(AssertUnwindSafe(call_action));
+    finalize(element);
+    if let Err(panic) = result {
+        std::panic::resume_unwind(panic);
+    }
+}
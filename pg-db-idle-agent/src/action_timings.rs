@@ -0,0 +1,83 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Buckets of powers of two microseconds, the last one takes everything from about 9 days up.
+const BUCKETS: usize = 40;
+
+/// How long a query's action took per row during a tick, see `PgDbAgentParams::with_time_actions`.
+/// Percentiles are the upper bound of a power-of-two bucket, so up to twice the real value but never above `max`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionTimings {
+    /// The query's name, or its text if it has none.
+    pub query: String,
+    /// Rows the action ran for.
+    pub count: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Action durations of one query since the last `take`, shared with the runs of its actions.
+pub(crate) struct ActionHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    max_micros: AtomicU64,
+}
+
+impl Default for ActionHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ActionHistogram {
+    /// Runs `call` and records how long it took.
+    pub(crate) fn time<R>(&self, call: impl FnOnce() -> R) -> R {
+        let started = Instant::now();
+        let result = call();
+        self.record(started.elapsed());
+        result
+    }
+
+    fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Timings recorded since the last call, `None` if the action didn't run.
+    pub(crate) fn take(&self, query: &str) -> Option<ActionTimings> {
+        let counts: Vec<u64> = self.buckets.iter().map(|bucket| bucket.swap(0, Ordering::Relaxed)).collect();
+        let max = Duration::from_micros(self.max_micros.swap(0, Ordering::Relaxed));
+        let count: u64 = counts.iter().sum();
+        if count == 0 {
+            return None;
+        }
+        let percentile = |percent: u64| {
+            let rank = (count * percent).div_ceil(100).max(1);
+            let mut seen = 0;
+            let bucket = counts
+                .iter()
+                .position(|bucket_count| {
+                    seen += bucket_count;
+                    seen >= rank
+                })
+                .unwrap_or(BUCKETS - 1);
+            Duration::from_micros(1u64 << bucket).min(max)
+        };
+        Some(ActionTimings {
+            query: query.to_string(),
+            count,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max,
+        })
+    }
+}
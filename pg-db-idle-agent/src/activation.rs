@@ -0,0 +1,17 @@
+/// Whether an agent acts on the rows it fetches, see `PgDbAgentParams::with_standby` and `AgentHandle::activate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationState {
+    /// Queries run and cursors advance, but actions don't.
+    Standby,
+    Active,
+}
+
+/// What a standby agent does with the rows its queries fetch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandbyPolicy {
+    /// Keep them in memory and hand them to the actions on the first tick after `activate`, before that tick's rows.
+    /// The buffer isn't bounded, so a long standby with a busy query holds every row it returned.
+    Buffer,
+    /// Discard them, rows fetched while on standby are never acted on.
+    Drop,
+}
@@ -0,0 +1,31 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Where `AgentEvent`s are written as JSON lines, e.g. a file or `std::io::stderr()`.
+pub type DebugSink = Arc<Mutex<dyn Write + Send>>;
+
+/// A significant step of the poll loop, written to the debug sink as one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AgentEvent {
+    TickStarted { due_only: bool },
+    RowsFetched { query: String, rows: usize },
+    ActionDone { query: String, index: usize },
+    Error { message: String },
+}
+
+impl AgentEvent {
+    /// Write failures are ignored, the sink is a debugging aid and must never fail a tick.
+    pub(crate) fn write_to(&self, sink: &DebugSink) {
+        let Ok(line) = serde_json::to_string(self) else {
+            return;
+        };
+        if let Ok(mut sink) = sink.lock() {
+            let _ = writeln!(sink, "{line}");
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture};
+use sqlx::postgres::PgRow;
+
+use crate::{AgentHandle, PgDbIdleAgent, RowAction, ShutdownOutcome, StartError};
+
+type Starter = Box<dyn FnOnce() -> BoxFuture<'static, Result<AgentHandle, StartError>> + Send>;
+
+/// Agents started, watched and shut down together, e.g. one per tenant or per concern. They keep running
+/// independently, one stopping or failing doesn't stop the others.
+#[derive(Default)]
+pub struct AgentGroup {
+    agents: Vec<(String, Starter)>,
+}
+
+impl AgentGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `agent` under `name`, which addresses it in the started group. Agents may poll different row types.
+    pub fn with_agent<T, F, E>(mut self, name: impl Into<String>, agent: PgDbIdleAgent<T, F, E>) -> Self
+    where
+        T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+        F: RowAction<T> + Send + Sync,
+        E: Fn(sqlx::Error) + Send + Sync + 'static,
+    {
+        self.agents.push((name.into(), Box::new(move || Box::pin(agent.start()))));
+        self
+    }
+
+    /// Starts every agent in the order they were added. If one fails to start, the ones already started are aborted.
+    pub async fn start(self) -> Result<AgentGroupHandle, StartError> {
+        let mut handles: Vec<(String, AgentHandle)> = Vec::with_capacity(self.agents.len());
+        for (name, start) in self.agents {
+            match start().await {
+                Ok(handle) => handles.push((name, handle)),
+                Err(e) => {
+                    handles.iter().for_each(|(_, handle)| handle.abort());
+                    return Err(e);
+                }
+            }
+        }
+        Ok(AgentGroupHandle { handles })
+    }
+}
+
+/// Handle to a started `AgentGroup`.
+pub struct AgentGroupHandle {
+    handles: Vec<(String, AgentHandle)>,
+}
+
+impl AgentGroupHandle {
+    /// Handle of the agent added under `name`, for everything the group doesn't do for all of them.
+    pub fn agent(&self, name: &str) -> Option<&AgentHandle> {
+        self.handles
+            .iter()
+            .find(|(agent_name, _)| agent_name == name)
+            .map(|(_, handle)| handle)
+    }
+
+    /// Names of the agents whose task is still running.
+    pub fn running(&self) -> Vec<&str> {
+        self.handles
+            .iter()
+            .filter(|(_, handle)| !handle.is_finished())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Whether every agent's task finished.
+    pub fn is_finished(&self) -> bool {
+        self.handles.iter().all(|(_, handle)| handle.is_finished())
+    }
+
+    /// Health of the group as a whole: healthy and running only if every agent is, with the longest time since a
+    /// successful tick and the most consecutive errors of any agent. See `agent` for the health of a single one.
+    #[cfg(feature = "health")]
+    pub fn health(&self, check: &crate::HealthCheck) -> crate::AgentHealth {
+        self.handles.iter().map(|(_, handle)| handle.health(check)).fold(
+            crate::AgentHealth {
+                healthy: true,
+                running: true,
+                since_last_successful_tick: Some(Duration::ZERO),
+                consecutive_errors: 0,
+            },
+            |group, agent| crate::AgentHealth {
+                healthy: group.healthy && agent.healthy,
+                running: group.running && agent.running,
+                since_last_successful_tick: group
+                    .since_last_successful_tick
+                    .zip(agent.since_last_successful_tick)
+                    .map(|(group, agent)| group.max(agent)),
+                consecutive_errors: group.consecutive_errors.max(agent.consecutive_errors),
+            },
+        )
+    }
+
+    /// `AgentHandle::trigger_now` on every agent.
+    pub fn trigger_now(&self) {
+        self.handles.iter().for_each(|(_, handle)| handle.trigger_now());
+    }
+
+    /// `AgentHandle::abort` on every agent.
+    pub fn abort(&self) {
+        self.handles.iter().for_each(|(_, handle)| handle.abort());
+    }
+
+    /// Shuts every agent down at once with `AgentHandle::shutdown` and waits for all of them, each within `timeout`.
+    /// Returns how each one ended, in the order they were added.
+    pub async fn shutdown(self, timeout: Duration) -> Vec<(String, ShutdownOutcome)> {
+        let (names, handles): (Vec<String>, Vec<AgentHandle>) = self.handles.into_iter().unzip();
+        let outcomes = future::join_all(handles.into_iter().map(|handle| handle.shutdown(timeout))).await;
+        names.into_iter().zip(outcomes).collect()
+    }
+}
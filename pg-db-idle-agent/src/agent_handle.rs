@@ -0,0 +1,361 @@
+use std::{
+    any::Any,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use arc_swap::ArcSwapOption;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::{
+    sync::Notify,
+    task::{JoinError, JoinHandle},
+    time::Instant,
+};
+
+use crate::{
+    drain::DrainRequests,
+    query_status::QueryShared,
+    recent_errors::RecentErrors,
+    row_buffer::RowBuffer,
+    task_end::{TaskEnd, TaskEndReason},
+    tick_report::TickWatch,
+    ActivationState, AgentState, BindValue, DrainError, DrainReport, ErrorRecord, ExplainError, OrderBy, OrderByError, QueryStatus, RemoveQueryError, TickReport,
+};
+
+/// State shared between the running agent and its `AgentHandle`.
+#[derive(Default)]
+pub(crate) struct AgentShared {
+    trigger: Notify,
+    trigger_pending: AtomicBool,
+    pub(crate) shutdown: Notify,
+    /// Wakes the loop to retire queries removed with `remove_query`.
+    pub(crate) removal: Notify,
+    /// Pool swapped in by `reconnect`, used instead of every configured pool when set.
+    pub(crate) pool: ArcSwapOption<PgPool>,
+    /// State of the params' accumulator, if one was registered.
+    pub(crate) accumulator: Option<Arc<dyn Any + Send + Sync>>,
+    pub(crate) queries: Vec<QueryShared>,
+    /// Failed ticks in a row, counted towards `max_consecutive_errors`.
+    pub(crate) consecutive_errors: AtomicU32,
+    /// `false` while on standby.
+    active: AtomicBool,
+    /// `true` while a tick runs its queries.
+    pub(crate) ticking: AtomicBool,
+    /// When the last tick without errors finished.
+    last_success: Mutex<Option<Instant>>,
+    /// How late the last scheduled tick fired.
+    drift: Mutex<Option<Duration>>,
+    pub(crate) ticks: TickWatch,
+    pub(crate) task_end: Mutex<TaskEnd>,
+    /// Rows fetched but not actioned yet, with `max_buffered_rows`.
+    pub(crate) row_buffer: Option<RowBuffer>,
+    pub(crate) drains: DrainRequests,
+    pub(crate) recent_errors: RecentErrors,
+}
+
+impl AgentShared {
+    pub(crate) fn new(
+        accumulator: Option<Arc<dyn Any + Send + Sync>>,
+        queries: Vec<QueryShared>,
+        active: bool,
+        row_buffer: Option<RowBuffer>,
+        recent_errors: RecentErrors,
+    ) -> Self {
+        Self {
+            accumulator,
+            queries,
+            active: AtomicBool::new(active),
+            row_buffer,
+            recent_errors,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        self.consecutive_errors.store(0, Ordering::Relaxed);
+        *self.last_success.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+    }
+
+    pub(crate) fn record_drift(&self, drift: Duration) {
+        *self.drift.lock().unwrap_or_else(PoisonError::into_inner) = Some(drift);
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    fn query(&self, name: &str) -> Option<&QueryShared> {
+        self.query_index(name).map(|index| &self.queries[index])
+    }
+
+    fn query_index(&self, name: &str) -> Option<usize> {
+        self.queries
+            .iter()
+            .position(|query| query.name.as_deref() == Some(name) && !query.is_removed())
+    }
+
+    /// Makes `pool` replace every configured pool, a pool swapped in earlier is closed once its in-flight queries finished.
+    pub(crate) fn swap_pool(&self, pool: PgPool) {
+        if let Some(previous) = self.pool.swap(Some(Arc::new(pool))) {
+            tokio::spawn(async move { previous.close().await });
+        }
+    }
+
+    /// Resolves once `trigger_now` was called. Triggers made before this consumed the pending one are merged into it.
+    pub(crate) async fn triggered(&self) {
+        self.trigger.notified().await;
+        self.trigger_pending.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Handle to a started agent.
+/// Awaiting it waits for the agent's task to finish, like awaiting the underlying `JoinHandle`.
+pub struct AgentHandle {
+    join_handle: JoinHandle<()>,
+    shared: Arc<AgentShared>,
+    #[cfg_attr(not(feature = "health"), allow(dead_code))]
+    started: Instant,
+}
+
+impl AgentHandle {
+    pub(crate) fn new(join_handle: JoinHandle<()>, shared: Arc<AgentShared>) -> Self {
+        Self {
+            join_handle,
+            shared,
+            started: Instant::now(),
+        }
+    }
+
+    /// Whether the agent is still running, ticked successfully within `check.max_tick_age` and hasn't failed more than
+    /// `check.max_consecutive_errors` ticks in a row. A standby agent that keeps polling counts as healthy.
+    #[cfg(feature = "health")]
+    pub fn health(&self, check: &crate::HealthCheck) -> crate::AgentHealth {
+        let last_success = *self.shared.last_success.lock().unwrap_or_else(PoisonError::into_inner);
+        let since_last_successful_tick = last_success.map(|last_success| last_success.elapsed());
+        let tick_age = since_last_successful_tick.unwrap_or_else(|| self.started.elapsed());
+        let consecutive_errors = self.shared.consecutive_errors.load(Ordering::Relaxed);
+        let running = !self.is_finished();
+        crate::AgentHealth {
+            healthy: running && tick_age <= check.max_tick_age && consecutive_errors <= check.max_consecutive_errors,
+            running,
+            since_last_successful_tick,
+            consecutive_errors,
+        }
+    }
+
+    /// Rows fetched and waiting for, or going through, their actions, `None` without `with_max_buffered_rows`.
+    /// Staying close to the limit means the actions are the bottleneck and fetches wait for them.
+    pub fn buffered_rows(&self) -> Option<usize> {
+        self.shared.row_buffer.as_ref().map(RowBuffer::occupancy)
+    }
+
+    /// Resolves with the next tick to finish without errors, failed ticks are waited past, or with `None` once the
+    /// agent's task ended, whether it stopped, panicked or was aborted.
+    pub async fn wait_for_tick(&self) -> Option<TickReport> {
+        self.shared.ticks.next().await
+    }
+
+    /// Runs every query right away instead of waiting for the next tick, then restarts the interval from now.
+    /// Triggers that arrive while a poll is pending or running coalesce into a single extra poll.
+    pub fn trigger_now(&self) {
+        if !self.shared.trigger_pending.swap(true, Ordering::SeqCst) {
+            self.shared.trigger.notify_one();
+        }
+    }
+
+    /// Asks the agent to stop after the tick it is currently running, if any, and waits up to `timeout` for it.
+    /// If the agent hasn't stopped by then its task is aborted, possibly in the middle of an action.
+    pub async fn shutdown(mut self, timeout: Duration) -> ShutdownOutcome {
+        self.shared.shutdown.notify_one();
+        match tokio::time::timeout(timeout, &mut self.join_handle).await {
+            Ok(_) => ShutdownOutcome::Clean,
+            Err(_) => {
+                self.join_handle.abort();
+                ShutdownOutcome::TimedOut
+            }
+        }
+    }
+
+    /// Repoints the agent at `new_url`, e.g. after a failover promoted a new primary or credentials were rotated.
+    /// From the next query on, every query, outbox and broadcast runs against the new pool instead of its own,
+    /// and actions get it as their `RowContext::write_pool`.
+    /// A pool from an earlier `reconnect` is closed once its in-flight queries finished,
+    /// the pools passed in the params are left open since the caller owns them.
+    pub async fn reconnect(&self, new_url: &str) -> Result<(), sqlx::Error> {
+        let pool = PgPoolOptions::new().connect(new_url).await?;
+        self.shared.swap_pool(pool);
+        Ok(())
+    }
+
+    /// Snapshot of the value registered with `PgDbAgentParams::with_accumulator`.
+    /// `None` if no accumulator was registered or it doesn't hold an `A`.
+    pub fn accumulator<A>(&self) -> Option<A>
+    where
+        A: Clone + 'static,
+    {
+        crate::accumulator::read(self.shared.accumulator.as_deref()?)
+    }
+
+    /// The last errors the agent reported, oldest first, e.g. for a debug endpoint. Always empty unless the params
+    /// set `with_recent_errors`.
+    pub fn recent_errors(&self) -> Vec<ErrorRecord> {
+        self.shared.recent_errors.snapshot()
+    }
+
+    /// Whether the agent is in the middle of a tick, i.e. running its queries and actions, e.g. to hold off a
+    /// `trigger_now` or a shutdown until it's idle. Use `wait_for_tick` to wait for the tick to finish.
+    pub fn is_ticking(&self) -> bool {
+        self.shared.ticking.load(Ordering::SeqCst)
+    }
+
+    /// How late the last scheduled tick fired compared to when the schedule planned it, `None` until one fired.
+    /// Growing drift means the loop can't keep up, e.g. because ticks take longer than the interval. Ticks run by a
+    /// trigger or notification aren't scheduled and don't count.
+    pub fn drift(&self) -> Option<Duration> {
+        *self.shared.drift.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Cursor of the query registered under `name`, the `cursor_bind` of the last row it fetched. `None` for unknown
+    /// queries, queries without a `cursor_bind` and ones that haven't returned rows (or been given a cursor) yet.
+    pub fn cursor(&self, name: &str) -> Option<BindValue> {
+        self.shared.query(name)?.cursor()
+    }
+
+    /// Status of the query registered under `name` with `PgDbAgentQueryActionParams::with_name`.
+    pub fn status_for(&self, name: &str) -> Option<QueryStatus> {
+        self.shared.query(name).map(QueryShared::status)
+    }
+
+    /// Promotes a standby agent to active and runs a tick right away, which hands the rows buffered on standby to the
+    /// actions. Does nothing on an agent that is already active.
+    pub fn activate(&self) {
+        if !self.shared.active.swap(true, Ordering::SeqCst) {
+            self.trigger_now();
+        }
+    }
+
+    pub fn activation_state(&self) -> ActivationState {
+        if self.shared.is_active() {
+            ActivationState::Active
+        } else {
+            ActivationState::Standby
+        }
+    }
+
+    /// Snapshot of the agent's progress, to be restored on a new agent with `PgDbIdleAgent::with_state`.
+    pub fn state(&self) -> AgentState {
+        AgentState {
+            cursors: self
+                .shared
+                .queries
+                .iter()
+                .filter_map(|query| Some((query.state_key.clone(), query.cursor()?)))
+                .collect(),
+            consecutive_errors: self.shared.consecutive_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// `EXPLAIN` output of the query registered under `name`, as it would run now (with `auto_limit` and the current
+    /// cursor applied) on its pool or the one swapped in by `reconnect`. `analyze` runs it for `EXPLAIN (ANALYZE)`,
+    /// inside a transaction that is rolled back.
+    pub async fn explain(&self, name: &str, analyze: bool) -> Result<String, ExplainError> {
+        let query = self
+            .shared
+            .query(name)
+            .ok_or_else(|| ExplainError::UnknownQuery(name.to_string()))?;
+        let reconnected_pool = self.shared.pool.load_full();
+        let pool = reconnected_pool.as_deref().unwrap_or(query.pool());
+        query.explain(pool, analyze).await.map_err(ExplainError::Database)
+    }
+
+    /// Pauses or resumes the query registered under `name`, a paused query is skipped on every tick.
+    /// Returns `false` if no query has that name.
+    pub fn set_query_enabled(&self, name: &str, enabled: bool) -> bool {
+        let Some(query) = self.shared.query(name) else {
+            return false;
+        };
+        query.set_enabled(enabled);
+        true
+    }
+
+    /// Current order of the query registered under `name`, `None` if there is none or it has no `with_order_by`.
+    pub fn order_by(&self, name: &str) -> Option<OrderBy> {
+        self.shared.query(name)?.order_by()
+    }
+
+    /// Switches the query registered under `name` to `order_by` from its next run on, e.g. to newest first while a
+    /// backlog builds up. The column must be one of those the query allowed in `with_order_by`, and a query with a
+    /// `cursor_bind` keeps its order.
+    pub fn set_order_by(&self, name: &str, order_by: OrderBy) -> Result<(), OrderByError> {
+        self.shared
+            .query(name)
+            .ok_or_else(|| OrderByError::UnknownQuery(name.to_string()))?
+            .set_order_by(order_by)
+    }
+
+    /// Runs the query registered under `name` over and over, ignoring its interval, until a run returns no rows, e.g. to
+    /// clear a backlog right now, then it goes back to its interval. Resolves with what the drain went through. Its
+    /// action must take rows out of the query's results, or the drain never ends. A run that fails ends the drain, as
+    /// does the query not running, e.g. because it was disabled. Other queries wait until the drain is done.
+    pub async fn drain_query(&self, name: &str) -> Result<DrainReport, DrainError> {
+        let index = self
+            .shared
+            .query_index(name)
+            .ok_or_else(|| DrainError::UnknownQuery(name.to_string()))?;
+        self.shared.drains.request(index).await.map_err(|_| DrainError::Stopped)
+    }
+
+    /// Retires the query registered under `name` for good without affecting the other queries: it is never scheduled
+    /// again, actions of its last run still going in their own task finish first, then its `on_stop` hook is called.
+    /// Unlike `set_query_enabled` there is no way back, the name no longer refers to a query afterwards.
+    pub fn remove_query(&self, name: &str) -> Result<(), RemoveQueryError> {
+        let query = self
+            .shared
+            .query(name)
+            .ok_or_else(|| RemoveQueryError::UnknownQuery(name.to_string()))?;
+        if query.remove() {
+            self.shared.removal.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Calls `hook` once the agent's task ended, however it did, right away if it already has. Unlike awaiting the
+    /// handle this also works for a supervisor that holds on to it to `abort` or `shutdown` the agent.
+    pub fn on_task_end<H>(&self, hook: H)
+    where
+        H: Fn(TaskEndReason) + Send + Sync + 'static,
+    {
+        TaskEnd::register(&self.shared.task_end, Box::new(hook));
+    }
+
+    pub fn abort(&self) {
+        self.join_handle.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
+    }
+}
+
+impl Future for AgentHandle {
+    type Output = Result<(), JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.join_handle).poll(cx)
+    }
+}
+
+/// How `AgentHandle::shutdown` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// The agent finished its current tick and stopped within the timeout.
+    Clean,
+    /// The timeout elapsed first and the agent's task was aborted.
+    TimedOut,
+}
@@ -0,0 +1,23 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::BindValue;
+
+/// Progress of a running agent that a new agent can pick up from, see `AgentHandle::state` and
+/// `PgDbIdleAgent::with_state`, e.g. to resume after a planned restart without processing rows twice.
+///
+/// The snapshot holds exactly:
+/// - `cursors`, the `cursor_bind` value of every query that fetched rows so far, keyed by the query's name or,
+///   for unnamed queries, its text,
+/// - `consecutive_errors`, the failed ticks in a row counted towards `max_consecutive_errors`.
+///
+/// Accumulators are typed by the caller, read them with `AgentHandle::accumulator` and pass them back as the
+/// initial value of `with_accumulator`. Totals, query statuses, interval timing and in-flight actions start fresh.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AgentState {
+    pub cursors: BTreeMap<String, BindValue>,
+    pub consecutive_errors: u32,
+}
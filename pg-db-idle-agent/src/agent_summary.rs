@@ -0,0 +1,40 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::StopReason;
+
+/// Lifetime totals of an agent, passed once to the `on_complete` hook when its loop stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgentSummary {
+    /// Ticks that ran, whether they succeeded or not.
+    pub ticks: u64,
+    /// Rows handed to query actions.
+    pub rows: u64,
+    /// Errors reported to the error handler.
+    pub errors: u64,
+    /// Time since `start` was called.
+    pub uptime: Duration,
+    pub stop_reason: StopReason,
+}
+
+/// Counters behind `AgentSummary`, updated by the poll loop as it goes.
+#[derive(Default)]
+pub(crate) struct AgentTotals {
+    pub(crate) ticks: AtomicU64,
+    pub(crate) rows: AtomicU64,
+    pub(crate) errors: AtomicU64,
+}
+
+impl AgentTotals {
+    pub(crate) fn summary(&self, uptime: Duration, stop_reason: StopReason) -> AgentSummary {
+        AgentSummary {
+            ticks: self.ticks.load(Ordering::Relaxed),
+            rows: self.rows.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            uptime,
+            stop_reason,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::{any::Any, sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+
+/// Identifies a row in its `AuditEvent`, e.g. its primary key.
+pub type AuditId<T> = Arc<dyn Fn(&T) -> String + Send + Sync>;
+
+/// Whether the action for a row went through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Success,
+    /// The action panicked or the handler returned an error, with its message.
+    Failure(String),
+}
+
+/// One row an action or handler ran for, see `PgDbAgentParams::with_audit_sink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// The query's name, or its text if it has none.
+    pub query: String,
+    /// From the query's `with_audit_id` extractor, `None` without one.
+    pub row_id: Option<String>,
+    /// When the action finished.
+    pub at: SystemTime,
+    /// Tick that fetched the row, `0` for handlers, which run outside of the ticks' count.
+    pub tick: u64,
+    pub outcome: AuditOutcome,
+}
+
+/// Destination of the audit trail, e.g. an append-only table or a compliance log shipper.
+///
+/// Awaited after every row's action, in order, so a slow sink slows the agent down rather than losing events.
+/// Implementations use `#[async_trait]`.
+#[async_trait]
+pub trait AuditSink: Send + Sync + 'static {
+    async fn record(&self, event: AuditEvent);
+}
+
+/// What a run needs to audit its rows.
+pub(crate) struct Audit<T> {
+    pub(crate) sink: Arc<dyn AuditSink>,
+    pub(crate) query: String,
+    pub(crate) id: Option<AuditId<T>>,
+}
+
+impl<T> Audit<T> {
+    pub(crate) fn row_id(&self, row: &T) -> Option<String> {
+        self.id.as_ref().map(|id| id(row))
+    }
+
+    pub(crate) async fn record(&self, row_id: Option<String>, tick: u64, outcome: AuditOutcome) {
+        self.sink
+            .record(AuditEvent {
+                query: self.query.clone(),
+                row_id,
+                at: SystemTime::now(),
+                tick,
+                outcome,
+            })
+            .await;
+    }
+}
+
+/// Message of a caught panic, for `AuditOutcome::Failure`.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => format!("panicked: {}", message),
+        (_, Some(message)) => format!("panicked: {}", message),
+        _ => "panicked".to_string(),
+    }
+}
@@ -0,0 +1,28 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgArguments, Arguments};
+
+/// A value bound to a query's `$1`, see `PgDbAgentQueryActionParams::with_cursor_bind`.
+/// Other column types can be passed as `Text` and cast in the query, e.g. `WHERE created_at > $1::timestamptz`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BindValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl BindValue {
+    pub(crate) fn arguments(bind: Option<&BindValue>) -> PgArguments {
+        let mut arguments = PgArguments::default();
+        match bind {
+            Some(BindValue::Bool(value)) => arguments.add(*value),
+            Some(BindValue::Int(value)) => arguments.add(*value),
+            Some(BindValue::Float(value)) => arguments.add(*value),
+            Some(BindValue::Text(value)) => arguments.add(value.clone()),
+            None => {}
+        }
+        arguments
+    }
+}
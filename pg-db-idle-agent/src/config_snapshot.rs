@@ -0,0 +1,343 @@
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use sqlx::postgres::PgRow;
+
+use crate::{PgDbAgentParams, PgDbAgentQueryActionParams, RowAction};
+
+/// The agent's effective configuration, see `PgDbIdleAgent::config_snapshot`. Hooks, actions and pools can't be
+/// described, only whether they are set, hooks by name in `hooks`. Policies and modes are their `Debug` output.
+///
+/// Every param is destructured without `..` when the snapshot is taken, so one added without a field here (or an
+/// explicit `_` saying why it's left out) doesn't build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct AgentConfigSnapshot {
+    pub interval: Duration,
+    /// Interval the agent actually ticks at, the shortest of the queries' intervals.
+    pub tick_interval: Duration,
+    /// `with_schedule`'s schedule, or `"custom"` with a `with_scheduler`.
+    pub schedule: String,
+    pub missed_tick_behavior: String,
+    pub max_catchup_ticks: Option<usize>,
+    pub max_consecutive_errors: Option<u32>,
+    pub max_total_rows: Option<u64>,
+    pub max_buffered_rows: Option<usize>,
+    pub max_ticks: Option<u64>,
+    pub time_budget_per_tick: Option<Duration>,
+    pub global_concurrency_limit: Option<usize>,
+    pub strict_priority: bool,
+    pub batch_queries: bool,
+    pub pgbouncer_compatible: bool,
+    pub pinned_connection: bool,
+    pub acquire_retry: Option<String>,
+    pub error_backoff: Option<String>,
+    pub standby: Option<String>,
+    pub pool_closed_policy: String,
+    pub notify_channels: Vec<String>,
+    pub time_actions: bool,
+    /// `with_on_drift_exceeded`'s threshold.
+    pub drift_threshold: Option<Duration>,
+    /// Errors kept for `AgentHandle::recent_errors`.
+    pub recent_errors: usize,
+    pub lag_ticks: u32,
+    pub should_tick: bool,
+    pub audit_sink: bool,
+    pub credential_provider: bool,
+    pub accumulator: bool,
+    /// Names of the agent-wide hooks that are set, e.g. `on_stop`.
+    pub hooks: Vec<String>,
+    #[cfg(feature = "cron")]
+    pub cron_timezone: String,
+    #[cfg(feature = "governor")]
+    pub rate_limiter: bool,
+    #[cfg(feature = "fault-injection")]
+    pub fault_injection: Option<String>,
+    #[cfg(feature = "serde")]
+    pub debug_sink: bool,
+    pub outboxes: usize,
+    pub broadcasts: usize,
+    pub shards: usize,
+    pub tenant_queries: usize,
+    pub raw_queries: usize,
+    pub handlers: usize,
+    pub sinks: usize,
+    pub queries: Vec<QueryConfigSnapshot>,
+}
+
+/// One query of an `AgentConfigSnapshot`, in the order they run in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct QueryConfigSnapshot {
+    pub name: Option<String>,
+    pub query: String,
+    /// The SQL actually sent, with its `ORDER BY` and `LIMIT`.
+    pub statement: String,
+    /// Interval the query is polled at, its own or the agent's.
+    pub interval: Duration,
+    pub priority: u8,
+    pub auto_limit: Option<usize>,
+    pub expected_max: Option<usize>,
+    pub limit_on_unexpected_volume: bool,
+    pub blocking_action: bool,
+    pub overlap_policy: Option<String>,
+    pub keyset: bool,
+    pub before_query: Vec<String>,
+    pub after_query: Vec<String>,
+    pub run_after: Vec<String>,
+    pub application_name: Option<String>,
+    pub expected_columns: Vec<String>,
+    pub separate_write_pool: bool,
+    /// Rows written back per statement, with a `with_write_back`.
+    pub write_batch_size: Option<usize>,
+    pub partitioned: bool,
+    /// The `DuplicatePolicy` rows are deduped with.
+    pub dedup: Option<String>,
+    pub dedup_sort: bool,
+    pub diff: bool,
+    pub pause_on_schema_drift: bool,
+    pub slow_query_threshold: Option<Duration>,
+    pub audit_id: bool,
+    pub size_of: bool,
+    /// Names of the query's hooks that are set, e.g. `on_empty`.
+    pub hooks: Vec<String>,
+}
+
+impl AgentConfigSnapshot {
+    pub(crate) fn new<T, F, E>(params: &PgDbAgentParams<T, F, E>) -> Self
+    where
+        T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+        F: RowAction<T>,
+    {
+        let tick_interval = params.tick_interval();
+        let PgDbAgentParams {
+            query_actions,
+            interval_secs,
+            error_handler: _,
+            notify,
+            outboxes,
+            broadcasts,
+            shards,
+            tenants,
+            raws,
+            handlers,
+            sinks,
+            max_consecutive_errors,
+            max_total_rows,
+            max_buffered_rows,
+            global_concurrency_limiter,
+            max_ticks,
+            should_tick,
+            strict_priority,
+            time_budget_per_tick,
+            batch_queries,
+            pgbouncer_compatible,
+            accumulator,
+            acquire_retry,
+            error_backoff,
+            // Always set, to `default_is_retryable` unless replaced.
+            is_retryable: _,
+            credential_provider,
+            audit_sink,
+            on_row_finalize,
+            recent_errors,
+            pinned_pool,
+            standby,
+            pool_closed_policy,
+            on_stop,
+            on_complete,
+            on_complete_query,
+            lag_ticks,
+            on_sustained_lag,
+            on_drift_exceeded,
+            on_cursor_advance,
+            on_tick_bytes,
+            time_actions,
+            on_action_timings,
+            schedule,
+            scheduler,
+            missed_tick_behavior,
+            max_catchup_ticks,
+            #[cfg(feature = "cron")]
+            cron_timezone,
+            #[cfg(feature = "governor")]
+            rate_limiter,
+            #[cfg(feature = "fault-injection")]
+            fault_injection,
+            #[cfg(feature = "serde")]
+            debug_sink,
+        } = params;
+        let schedule = match scheduler {
+            Some(_) => "custom".to_string(),
+            None => format!("{:?}", schedule),
+        };
+        let hooks = [
+            ("on_row_finalize", on_row_finalize.is_some()),
+            ("on_stop", on_stop.is_some()),
+            ("on_complete", on_complete.is_some()),
+            ("on_complete_query", on_complete_query.is_some()),
+            ("on_sustained_lag", on_sustained_lag.is_some()),
+            ("on_cursor_advance", on_cursor_advance.is_some()),
+            ("on_tick_bytes", on_tick_bytes.is_some()),
+            ("on_action_timings", on_action_timings.is_some()),
+        ];
+        Self {
+            interval: *interval_secs,
+            tick_interval,
+            schedule,
+            missed_tick_behavior: format!("{:?}", missed_tick_behavior),
+            max_catchup_ticks: *max_catchup_ticks,
+            max_consecutive_errors: *max_consecutive_errors,
+            max_total_rows: *max_total_rows,
+            max_buffered_rows: *max_buffered_rows,
+            max_ticks: *max_ticks,
+            time_budget_per_tick: *time_budget_per_tick,
+            global_concurrency_limit: global_concurrency_limiter.as_ref().map(|limiter| limiter.max()),
+            strict_priority: *strict_priority,
+            batch_queries: *batch_queries,
+            pgbouncer_compatible: *pgbouncer_compatible,
+            pinned_connection: pinned_pool.is_some(),
+            acquire_retry: acquire_retry.map(|retry| format!("{:?}", retry)),
+            error_backoff: error_backoff.map(|error_backoff| format!("{:?}", error_backoff)),
+            standby: standby.map(|standby| format!("{:?}", standby)),
+            pool_closed_policy: format!("{:?}", pool_closed_policy),
+            notify_channels: notify
+                .as_ref()
+                .map(|notify| notify.channels.iter().chain(notify.channel_queries.keys()).cloned().collect())
+                .unwrap_or_default(),
+            time_actions: *time_actions,
+            drift_threshold: on_drift_exceeded.as_ref().map(|(threshold, _)| *threshold),
+            recent_errors: *recent_errors,
+            lag_ticks: *lag_ticks,
+            should_tick: should_tick.is_some(),
+            audit_sink: audit_sink.is_some(),
+            credential_provider: credential_provider.is_some(),
+            accumulator: accumulator.is_some(),
+            hooks: set_hooks(hooks),
+            #[cfg(feature = "cron")]
+            cron_timezone: cron_timezone.to_string(),
+            #[cfg(feature = "governor")]
+            rate_limiter: rate_limiter.is_some(),
+            #[cfg(feature = "fault-injection")]
+            fault_injection: fault_injection.map(|fault_injection| format!("{:?}", fault_injection)),
+            #[cfg(feature = "serde")]
+            debug_sink: debug_sink.is_some(),
+            outboxes: outboxes.len(),
+            broadcasts: broadcasts.len(),
+            shards: shards.len(),
+            tenant_queries: tenants.len(),
+            raw_queries: raws.len(),
+            handlers: handlers.len(),
+            sinks: sinks.len(),
+            queries: query_actions
+                .iter()
+                .map(|param| QueryConfigSnapshot::new(param, params.effective_interval(param)))
+                .collect(),
+        }
+    }
+}
+
+impl QueryConfigSnapshot {
+    fn new<T, F>(param: &PgDbAgentQueryActionParams<T, F>, interval: Duration) -> Self
+    where
+        T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+        F: RowAction<T>,
+    {
+        let statement = param.statement().into_owned();
+        let PgDbAgentQueryActionParams {
+            pool: _,
+            write_pool,
+            query,
+            name,
+            action: _,
+            // Replaced by the effective `interval`.
+            interval: _,
+            auto_limit,
+            blocking_action,
+            overlap_policy,
+            on_decode_error,
+            on_schema_drift,
+            pause_on_schema_drift,
+            cursor_bind,
+            before_query,
+            after_query,
+            run_after,
+            application_name,
+            audit_id,
+            write_back,
+            on_became_empty,
+            on_became_nonempty,
+            on_empty,
+            on_recovered,
+            size_of,
+            slow_query_threshold,
+            // Set together with `slow_query_threshold`.
+            on_slow_query: _,
+            priority,
+            partition_key,
+            dedup,
+            dedup_sort,
+            on_stop,
+            expected_columns,
+            expected_max,
+            // Set together with `expected_max`.
+            on_unexpected_volume: _,
+            limit_on_unexpected_volume,
+            // State of the running agent rather than configuration.
+            volume_limit: _,
+            dedup_policy,
+            diff,
+            // Part of the `statement`.
+            ordering: _,
+            _marker: _,
+        } = param;
+        let hooks = [
+            ("on_decode_error", on_decode_error.is_some()),
+            ("on_schema_drift", on_schema_drift.is_some()),
+            ("on_became_empty", on_became_empty.is_some()),
+            ("on_became_nonempty", on_became_nonempty.is_some()),
+            ("on_empty", on_empty.is_some()),
+            ("on_recovered", on_recovered.is_some()),
+            ("on_stop", on_stop.is_some()),
+        ];
+        Self {
+            name: name.clone(),
+            query: query.clone(),
+            statement,
+            interval,
+            priority: *priority,
+            auto_limit: *auto_limit,
+            expected_max: *expected_max,
+            limit_on_unexpected_volume: *limit_on_unexpected_volume,
+            blocking_action: *blocking_action,
+            overlap_policy: overlap_policy.map(|overlap_policy| format!("{:?}", overlap_policy)),
+            keyset: cursor_bind.is_some(),
+            before_query: before_query.clone(),
+            after_query: after_query.clone(),
+            run_after: run_after.clone(),
+            application_name: application_name.clone(),
+            expected_columns: expected_columns.iter().map(|column| column.name.clone()).collect(),
+            separate_write_pool: write_pool.is_some(),
+            write_batch_size: write_back.as_ref().map(|write_back| write_back.write_batch_size),
+            partitioned: partition_key.is_some(),
+            dedup: dedup.as_ref().map(|_| format!("{:?}", dedup_policy)),
+            dedup_sort: dedup_sort.is_some(),
+            diff: diff.is_some(),
+            pause_on_schema_drift: *pause_on_schema_drift,
+            slow_query_threshold: *slow_query_threshold,
+            audit_id: audit_id.is_some(),
+            size_of: size_of.is_some(),
+            hooks: set_hooks(hooks),
+        }
+    }
+}
+
+/// Names of the hooks that are set.
+fn set_hooks<const N: usize>(hooks: [(&str, bool); N]) -> Vec<String> {
+    hooks
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
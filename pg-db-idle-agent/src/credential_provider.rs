@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+use sqlx::postgres::PgConnectOptions;
+
+/// Source of fresh connection options, e.g. credentials fetched from a secrets manager that rotates them.
+///
+/// When a tick fails with an authentication error the agent asks the provider for new options, builds a pool
+/// from them and swaps it in like `AgentHandle::reconnect` does.
+#[async_trait]
+pub trait CredentialProvider: Send + Sync + 'static {
+    async fn connect_options(&self) -> Result<PgConnectOptions, sqlx::Error>;
+}
+
+/// `invalid_authorization_specification` (e.g. unknown role) or `invalid_password`.
+pub(crate) fn is_auth_error(e: &sqlx::Error) -> bool {
+    let Some(e) = e.as_database_error() else {
+        return false;
+    };
+    matches!(e.code().as_deref(), Some("28000" | "28P01"))
+}
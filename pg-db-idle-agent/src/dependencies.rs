@@ -0,0 +1,79 @@
+use sqlx::postgres::PgRow;
+
+use crate::{ParamsError, PgDbAgentQueryActionParams, RowAction};
+
+/// Indices of `query_actions` in the order they have to run in: every query after the ones it names in
+/// `with_run_after`, otherwise in the order they are in. Fails on a name no query has and on a cycle.
+pub(crate) fn run_order<T, F>(query_actions: &[PgDbAgentQueryActionParams<T, F>]) -> Result<Vec<usize>, ParamsError>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T>,
+{
+    run_order_by(query_actions, |index| index)
+}
+
+/// Like `run_order`, but of the queries whose dependencies are placed the one with the smallest `key` goes next.
+pub(crate) fn run_order_by<T, F, K>(
+    query_actions: &[PgDbAgentQueryActionParams<T, F>],
+    key: impl Fn(usize) -> K,
+) -> Result<Vec<usize>, ParamsError>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T>,
+    K: Ord,
+{
+    let mut dependencies = Vec::with_capacity(query_actions.len());
+    for query_action in query_actions {
+        let mut indices = Vec::new();
+        for after in &query_action.run_after {
+            let named: Vec<usize> = query_actions
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other.name.as_ref() == Some(after))
+                .map(|(index, _)| index)
+                .collect();
+            if named.is_empty() {
+                return Err(ParamsError::UnknownDependency {
+                    query: query_action.query.clone(),
+                    after: after.clone(),
+                });
+            }
+            indices.extend(named);
+        }
+        dependencies.push(indices);
+    }
+    let mut placed = vec![false; query_actions.len()];
+    let mut order = Vec::with_capacity(query_actions.len());
+    while order.len() < query_actions.len() {
+        let next = (0..query_actions.len())
+            .filter(|&index| !placed[index] && dependencies[index].iter().all(|&dependency| placed[dependency]))
+            .min_by_key(|&index| key(index));
+        let Some(next) = next else {
+            return Err(ParamsError::DependencyCycle {
+                queries: (0..query_actions.len())
+                    .filter(|&index| !placed[index])
+                    .map(|index| {
+                        let query_action = &query_actions[index];
+                        query_action.name.clone().unwrap_or_else(|| query_action.query.clone())
+                    })
+                    .collect(),
+            });
+        };
+        placed[next] = true;
+        order.push(next);
+    }
+    Ok(order)
+}
+
+/// Puts `query_actions` in the order of `run_order`, as they are if that fails.
+pub(crate) fn sort<T, F>(query_actions: &mut Vec<PgDbAgentQueryActionParams<T, F>>)
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: RowAction<T>,
+{
+    let Ok(order) = run_order(query_actions) else {
+        return;
+    };
+    let mut unsorted: Vec<_> = std::mem::take(query_actions).into_iter().map(Some).collect();
+    query_actions.extend(order.into_iter().filter_map(|index| unsorted[index].take()));
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashSet,
+    sync::{Mutex, PoisonError},
+};
+
+use tokio::sync::{oneshot, Notify};
+
+use crate::query_status::QueryShared;
+
+/// How a drain started with `AgentHandle::drain_query` went.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    /// Runs of the query during the drain, the last one returned no rows unless it failed.
+    pub runs: u64,
+    /// Rows those runs returned, handed to the query's action.
+    pub rows: u64,
+    /// Error of the run that ended the drain early, `None` if it ran until the query returned no rows.
+    pub error: Option<String>,
+}
+
+/// Drains asked for by `AgentHandle::drain_query`, waiting for the loop to pick them up.
+#[derive(Default)]
+pub(crate) struct DrainRequests {
+    pending: Mutex<PendingDrains>,
+    pub(crate) requested: Notify,
+}
+
+#[derive(Default)]
+struct PendingDrains {
+    requests: Vec<(usize, oneshot::Sender<DrainReport>)>,
+    /// Set once the agent's task ended, nothing picks requests up anymore.
+    closed: bool,
+}
+
+impl DrainRequests {
+    /// The report of the requested drain, the sender is dropped right away once the agent's task ended.
+    pub(crate) fn request(&self, query: usize) -> oneshot::Receiver<DrainReport> {
+        let (report, on_report) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if pending.closed {
+            return on_report;
+        }
+        pending.requests.push((query, report));
+        drop(pending);
+        self.requested.notify_one();
+        on_report
+    }
+
+    /// Drops the requests the loop didn't pick up and turns away new ones, called when the agent's task ends.
+    pub(crate) fn close(&self) {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        pending.closed = true;
+        let requests = std::mem::take(&mut pending.requests);
+        drop(pending);
+        drop(requests);
+    }
+
+    fn take(&self) -> Vec<(usize, oneshot::Sender<DrainReport>)> {
+        std::mem::take(&mut self.pending.lock().unwrap_or_else(PoisonError::into_inner).requests)
+    }
+}
+
+/// A query running back to back until it comes back empty.
+struct Drain {
+    query: usize,
+    /// The query's `QueryStatus::runs` when the drain last looked, to tell whether it ran since.
+    runs_seen: u64,
+    report: DrainReport,
+    /// Every request for the query's drain gets the same report.
+    waiting: Vec<oneshot::Sender<DrainReport>>,
+}
+
+/// The drains the loop is running.
+#[derive(Default)]
+pub(crate) struct Drains {
+    drains: Vec<Drain>,
+}
+
+impl Drains {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.drains.is_empty()
+    }
+
+    /// Starts the requested drains, a query already draining keeps its drain.
+    pub(crate) fn accept(&mut self, requests: &DrainRequests, queries: &[QueryShared]) {
+        for (query, report) in requests.take() {
+            match self.drains.iter_mut().find(|drain| drain.query == query) {
+                Some(drain) => drain.waiting.push(report),
+                None => self.drains.push(Drain {
+                    query,
+                    runs_seen: queries[query].status().runs,
+                    report: DrainReport::default(),
+                    waiting: vec![report],
+                }),
+            }
+        }
+    }
+
+    /// Names of the draining queries, for the scope of the next tick.
+    pub(crate) fn names(&self, queries: &[QueryShared]) -> HashSet<String> {
+        self.drains
+            .iter()
+            .filter_map(|drain| queries[drain.query].name.clone())
+            .collect()
+    }
+
+    /// Counts the tick's run of every draining query and finishes the drains of queries that returned no rows,
+    /// failed, or didn't run at all, e.g. because they were disabled or removed.
+    pub(crate) fn record_tick(&mut self, queries: &[QueryShared]) {
+        self.drains.retain_mut(|drain| {
+            let status = queries[drain.query].status();
+            let done = if status.runs == drain.runs_seen {
+                true
+            } else {
+                drain.runs_seen = status.runs;
+                drain.report.runs += 1;
+                drain.report.error = status.last_error;
+                match (&drain.report.error, status.last_row_count) {
+                    (None, Some(rows)) => {
+                        drain.report.rows += rows as u64;
+                        rows == 0
+                    }
+                    _ => true,
+                }
+            };
+            if done {
+                for waiting in drain.waiting.drain(..) {
+                    let _ = waiting.send(drain.report.clone());
+                }
+            }
+            !done
+        });
+    }
+}
@@ -0,0 +1,11 @@
+/// Which of a run's rows with the same key `with_dedup_policy` keeps, e.g. when a join returns an entity once per
+/// joined row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// The first row of each key in the run's order.
+    #[default]
+    KeepFirst,
+    /// The last row of each key, e.g. the latest version when rows are ordered by time. Kept rows stay in the order
+    /// of their last occurrence.
+    KeepLast,
+}
@@ -0,0 +1,204 @@
+/// Error returned by fallible actions. Any error type can be boxed into it with `?` or `.into()`.
+pub type ActionError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Error returned by a `RowSink`.
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Invalid agent params, returned by `PgDbAgentParams::new`, `PgDbAgentParams::validate_pools` and
+/// `PgDbAgentParams::validate_columns`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamsError {
+    /// The agent's `interval_secs` is zero.
+    ZeroInterval,
+    /// The interval override of this query is zero.
+    ZeroQueryInterval { query: String },
+    /// No connection could be acquired from a pool this query uses, see `PgDbAgentParams::validate_pools`.
+    UnusablePool { query: String, reason: String },
+    /// This query dedups its rows but neither has an `ORDER BY` nor a `with_dedup_sort`.
+    UnorderedDedup { query: String },
+    /// This query doesn't return the columns it declared with `with_expected_columns`,
+    /// see `PgDbAgentParams::validate_columns`.
+    ColumnMismatch { query: String, reason: String },
+    /// The `OrderBy` this query was configured with sorts by a column missing from its allowed columns.
+    OrderByNotAllowed { query: String, column: String },
+    /// This query runs after `after`, a name none of the queries has, see `with_run_after`.
+    UnknownDependency { query: String, after: String },
+    /// These queries run after each other in a circle, so none of them can go first.
+    DependencyCycle { queries: Vec<String> },
+    /// This query prefetches with `OverlapPolicy::Prefetch` but has no `with_cursor_bind`, so it would fetch the rows
+    /// the previous run is still processing again.
+    PrefetchWithoutCursor { query: String },
+    /// Notifications on `channel` run the query named `name`, but no query has that name, see `with_channel_query`.
+    UnknownChannelQuery { channel: String, name: String },
+}
+
+impl std::fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroInterval => write!(f, "the agent interval must be greater than zero"),
+            Self::ZeroQueryInterval { query } => {
+                write!(f, "the interval of query `{}` must be greater than zero", query)
+            }
+            Self::UnusablePool { query, reason } => {
+                write!(f, "the pool of query `{}` is unusable: {}", query, reason)
+            }
+            Self::UnorderedDedup { query } => {
+                write!(f, "query `{}` dedups its rows without a deterministic order, add an ORDER BY or a dedup sort", query)
+            }
+            Self::ColumnMismatch { query, reason } => {
+                write!(f, "query `{}` doesn't match its row type: {}", query, reason)
+            }
+            Self::OrderByNotAllowed { query, column } => {
+                write!(f, "query `{}` can't be ordered by `{}`, it isn't one of its allowed columns", query, column)
+            }
+            Self::UnknownDependency { query, after } => {
+                write!(f, "query `{}` runs after `{}`, but no query has that name", query, after)
+            }
+            Self::DependencyCycle { queries } => {
+                write!(f, "queries {} run after each other in a cycle", queries.join(", "))
+            }
+            Self::PrefetchWithoutCursor { query } => {
+                write!(f, "query `{}` prefetches without a cursor bind, it would fetch the rows being processed again", query)
+            }
+            Self::UnknownChannelQuery { channel, name } => {
+                write!(f, "channel `{}` runs query `{}`, but no query has that name", channel, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+/// Error starting an agent, returned by `PgDbIdleAgent::start`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartError {
+    /// `start` was called outside of a Tokio runtime, so there is nothing to spawn the agent's loop on.
+    NoRuntime,
+}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoRuntime => write!(f, "the agent must be started from within a Tokio runtime"),
+        }
+    }
+}
+
+impl std::error::Error for StartError {}
+
+/// Error explaining a query, returned by `AgentHandle::explain`.
+#[derive(Debug)]
+pub enum ExplainError {
+    /// No query was registered under this name.
+    UnknownQuery(String),
+    /// Running `EXPLAIN` failed.
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for ExplainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownQuery(name) => write!(f, "no query named `{}`", name),
+            Self::Database(e) => write!(f, "failed to explain the query: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExplainError {}
+
+/// Error removing a query, returned by `AgentHandle::remove_query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoveQueryError {
+    /// No query is registered under this name, or it was removed already.
+    UnknownQuery(String),
+}
+
+impl std::fmt::Display for RemoveQueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownQuery(name) => write!(f, "no query named `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for RemoveQueryError {}
+
+/// Error returned by `AgentHandle::drain_query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DrainError {
+    /// No query is registered under this name.
+    UnknownQuery(String),
+    /// The agent stopped before the drain was done.
+    Stopped,
+}
+
+impl std::fmt::Display for DrainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownQuery(name) => write!(f, "no query named `{}`", name),
+            Self::Stopped => write!(f, "the agent stopped before the drain was done"),
+        }
+    }
+}
+
+impl std::error::Error for DrainError {}
+
+/// Error returned by `AgentHandle::set_order_by`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderByError {
+    /// No query is registered under this name.
+    UnknownQuery(String),
+    /// The query wasn't configured with `with_order_by`.
+    Unordered(String),
+    /// The column isn't one of the query's allowed columns.
+    ColumnNotAllowed { query: String, column: String },
+    /// The query has a `cursor_bind`, whose keyset condition only works in the order it was written for.
+    KeysetQuery(String),
+}
+
+impl std::fmt::Display for OrderByError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownQuery(name) => write!(f, "no query named `{}`", name),
+            Self::Unordered(name) => write!(f, "query `{}` has no configurable order", name),
+            Self::ColumnNotAllowed { query, column } => {
+                write!(f, "query `{}` can't be ordered by `{}`, it isn't one of its allowed columns", query, column)
+            }
+            Self::KeysetQuery(name) => {
+                write!(f, "query `{}` pages with a cursor, its order can't change while it runs", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderByError {}
+
+/// Error building agent params from a `PgDbAgentConfig`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A query refers to an action name that was never registered.
+    UnknownAction(String),
+    /// The configured values don't make valid params.
+    InvalidParams(ParamsError),
+}
+
+#[cfg(feature = "serde")]
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownAction(name) => write!(f, "no action registered under the name `{}`", name),
+            Self::InvalidParams(e) => write!(f, "invalid agent config: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for ConfigError {}
+
+#[cfg(feature = "serde")]
+impl From<ParamsError> for ConfigError {
+    fn from(e: ParamsError) -> Self {
+        Self::InvalidParams(e)
+    }
+}
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+/// Polls less often while ticks keep failing, to take load off a struggling database, see
+/// `PgDbAgentParams::with_error_backoff`.
+///
+/// Every failed tick multiplies the interval by `factor`, up to `max_interval`. The first tick without errors goes
+/// back to the configured interval. Unlike `with_max_consecutive_errors` the agent never gives up, it only slows down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorBackoff {
+    pub factor: f64,
+    pub max_interval: Duration,
+}
+
+impl ErrorBackoff {
+    pub fn new(factor: f64, max_interval: Duration) -> Self {
+        Self { factor, max_interval }
+    }
+
+    /// Interval after a tick polled at `interval` failed. Never shorter than `interval`, so a `factor` below 1 or a
+    /// `max_interval` below the configured interval leave it as is.
+    pub(crate) fn next_interval(&self, interval: Duration) -> Duration {
+        let next = interval.as_secs_f64() * self.factor;
+        // A negative or NaN `factor` leaves the interval as is, only an overflow means `max_interval`.
+        if next.is_nan() || next < 0.0 {
+            return interval;
+        }
+        Duration::try_from_secs_f64(next)
+            .unwrap_or(self.max_interval)
+            .min(self.max_interval)
+            .max(interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_backoff_next_interval() {
+        let interval = Duration::from_secs(10);
+        let next_interval = |factor| ErrorBackoff::new(factor, Duration::from_secs(60)).next_interval(interval);
+        assert_eq!(next_interval(2.0), Duration::from_secs(20));
+        assert_eq!(next_interval(10.0), Duration::from_secs(60));
+        assert_eq!(next_interval(f64::INFINITY), Duration::from_secs(60));
+        assert_eq!(next_interval(0.5), interval);
+        assert_eq!(next_interval(-1.0), interval);
+        assert_eq!(next_interval(f64::NAN), interval);
+    }
+}
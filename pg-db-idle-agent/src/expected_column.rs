@@ -0,0 +1,59 @@
+use sqlx::{Column, Executor, PgPool, Statement, TypeInfo};
+
+/// A column the row type decodes, checked against what the query returns by `PgDbAgentParams::validate_columns`.
+/// Converts from a name (`"id"`), or a name and a Postgres type name (`("id", "INT4")`) to check the type too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedColumn {
+    pub name: String,
+    /// Postgres type name as in `PgTypeInfo::name`, e.g. `INT4` or `TEXT`, compared case-insensitively.
+    pub type_name: Option<String>,
+}
+
+impl From<&str> for ExpectedColumn {
+    fn from(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            type_name: None,
+        }
+    }
+}
+
+impl From<(&str, &str)> for ExpectedColumn {
+    fn from((name, type_name): (&str, &str)) -> Self {
+        Self {
+            name: name.to_string(),
+            type_name: Some(type_name.to_string()),
+        }
+    }
+}
+
+/// Prepares `statement` without running it and describes the first expected column it doesn't return as expected.
+/// Extra columns are fine, `FromRow` ignores them.
+pub(crate) async fn mismatch(pool: &PgPool, statement: &str, expected: &[ExpectedColumn]) -> Option<String> {
+    let prepared = match pool.prepare(statement).await {
+        Ok(prepared) => prepared,
+        Err(e) => return Some(format!("failed to describe the query: {}", e)),
+    };
+    let columns = prepared.columns();
+    for expected in expected {
+        let Some(column) = columns.iter().find(|column| column.name() == expected.name) else {
+            let returned = columns.iter().map(|column| column.name()).collect::<Vec<_>>().join(", ");
+            return Some(format!("missing column `{}`, the query returns: {}", expected.name, returned));
+        };
+        let actual = column.type_info().name();
+        if let Some(type_name) = expected.type_name.as_deref().filter(|type_name| !actual.eq_ignore_ascii_case(type_name)) {
+            return Some(format!("column `{}` is {}, expected {}", expected.name, actual, type_name));
+        }
+    }
+    None
+}
+
+/// Whether `e` means the query no longer returns the columns the row type decodes, e.g. after a migration: a column is
+/// missing or of another type, or the query names a column (SQLSTATE 42703) that was dropped.
+pub(crate) fn is_schema_drift(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::ColumnNotFound(_) | sqlx::Error::ColumnDecode { .. } | sqlx::Error::ColumnIndexOutOfBounds { .. } => true,
+        sqlx::Error::Database(e) => e.code().is_some_and(|code| code == "42703"),
+        _ => false,
+    }
+}
@@ -0,0 +1,68 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Makes ticks fail on purpose, to exercise error handlers, retries and circuit breakers,
+/// see `PgDbAgentParams::with_fault_injection`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultConfig {
+    /// Chance of every tick to fail before running any query, clamped to `0.0..=1.0`, NaN never fails.
+    pub error_probability: f64,
+    /// Seed of the random generator, for a reproducible sequence of failures. Seeded from the OS when `None`.
+    pub seed: Option<u64>,
+}
+
+impl FaultConfig {
+    pub fn new(error_probability: f64) -> Self {
+        Self {
+            error_probability,
+            seed: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+pub(crate) struct FaultInjector {
+    error_probability: f64,
+    rng: StdRng,
+}
+
+impl FaultInjector {
+    pub(crate) fn new(config: FaultConfig) -> Self {
+        Self {
+            error_probability: match config.error_probability {
+                probability if probability.is_nan() => 0.0,
+                probability => probability.clamp(0.0, 1.0),
+            },
+            rng: config.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+        }
+    }
+
+    /// Rolls for the next tick, a synthetic I/O error fails it just like a dropped connection would.
+    pub(crate) fn roll(&mut self) -> Result<(), sqlx::Error> {
+        if self.rng.gen_bool(self.error_probability) {
+            return Err(sqlx::Error::Io(std::io::Error::other("injected fault")));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_injector_probability() {
+        let error_probability = |probability| FaultInjector::new(FaultConfig::new(probability)).error_probability;
+        assert_eq!(error_probability(f64::NAN), 0.0);
+        assert_eq!(error_probability(-1.0), 0.0);
+        assert_eq!(error_probability(f64::INFINITY), 1.0);
+        assert_eq!(error_probability(0.25), 0.25);
+
+        // Never fails, rather than panicking in `gen_bool`.
+        let mut injector = FaultInjector::new(FaultConfig::new(f64::NAN).with_seed(7));
+        assert!((0..100).all(|_| injector.roll().is_ok()));
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::{Arc, OnceLock};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+static PROCESS: OnceLock<GlobalConcurrencyLimiter> = OnceLock::new();
+
+/// Ceiling on the queries running at once across every agent holding a clone of it, see
+/// `PgDbAgentParams::with_global_concurrency_limiter`.
+///
+/// Clones share their permits, so create one and hand a clone to every agent, or have agents built in unrelated parts
+/// of the program call `GlobalConcurrencyLimiter::process`, which returns the same limiter everywhere.
+#[derive(Clone)]
+pub struct GlobalConcurrencyLimiter {
+    max: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl GlobalConcurrencyLimiter {
+    /// A limiter letting `max` queries run at once, at least one.
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            permits: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    /// The limiter of the whole process, created with `max` by the first call. Later calls return that limiter and
+    /// ignore their `max`, so every call site should pass the same value, e.g. from one config setting.
+    pub fn process(max: usize) -> Self {
+        PROCESS.get_or_init(|| Self::new(max)).clone()
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Queries holding a permit right now.
+    pub fn in_use(&self) -> usize {
+        self.max - self.permits.available_permits()
+    }
+
+    /// Waits for a permit, held by the query until it's dropped.
+    pub(crate) async fn acquire(&self) -> OwnedSemaphorePermit {
+        Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .expect("the limiter's semaphore is never closed")
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Thresholds for `AgentHandle::health`, e.g. from the readiness probe's configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Longest time without a successful tick that is still healthy, usually a few tick intervals.
+    /// Before the first successful tick the time since `start` counts instead.
+    pub max_tick_age: Duration,
+    /// Most failed ticks in a row that are still healthy.
+    pub max_consecutive_errors: u32,
+}
+
+impl HealthCheck {
+    pub fn new(max_tick_age: Duration, max_consecutive_errors: u32) -> Self {
+        Self {
+            max_tick_age,
+            max_consecutive_errors,
+        }
+    }
+}
+
+/// Health of a running agent, for a liveness or readiness endpoint of the service embedding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct AgentHealth {
+    pub healthy: bool,
+    /// `false` once the agent's loop stopped or its task was aborted.
+    pub running: bool,
+    /// `None` until a tick succeeded.
+    pub since_last_successful_tick: Option<Duration>,
+    pub consecutive_errors: u32,
+}
+
+impl AgentHealth {
+    /// `200` when healthy, `503` otherwise, to answer the probe with.
+    pub fn status_code(&self) -> u16 {
+        if self.healthy {
+            200
+        } else {
+            503
+        }
+    }
+}
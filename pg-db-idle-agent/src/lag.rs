@@ -0,0 +1,60 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio::time::Instant;
+
+/// Passed to the `on_sustained_lag` hook once ticks have been taking longer than the tick interval for a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LagReport {
+    /// Moving average of the last `lag_ticks` tick durations.
+    pub average_tick_duration: Duration,
+    /// The interval the average is compared against.
+    pub tick_interval: Duration,
+    /// Time since the first tick of the current lagging streak started.
+    pub lagging_for: Duration,
+    /// Number of consecutive ticks the moving average exceeded the interval.
+    pub lagging_ticks: u32,
+}
+
+/// Tracks tick durations and reports once per lagging streak, i.e. when the moving average over the last
+/// `lag_ticks` ticks has exceeded the tick interval for `lag_ticks` consecutive ticks.
+pub(crate) struct LagTracker {
+    lag_ticks: u32,
+    tick_interval: Duration,
+    durations: VecDeque<Duration>,
+    lagging_since: Option<Instant>,
+    lagging_ticks: u32,
+}
+
+impl LagTracker {
+    pub(crate) fn new(lag_ticks: u32, tick_interval: Duration) -> Self {
+        Self {
+            lag_ticks: lag_ticks.max(1),
+            tick_interval,
+            durations: VecDeque::new(),
+            lagging_since: None,
+            lagging_ticks: 0,
+        }
+    }
+
+    /// Records a tick that started at `started` and just finished, returns a report when the streak reaches `lag_ticks`.
+    pub(crate) fn record(&mut self, started: Instant) -> Option<LagReport> {
+        if self.durations.len() == self.lag_ticks as usize {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(started.elapsed());
+        let average_tick_duration = self.durations.iter().sum::<Duration>() / self.durations.len() as u32;
+        if average_tick_duration <= self.tick_interval {
+            self.lagging_since = None;
+            self.lagging_ticks = 0;
+            return None;
+        }
+        let lagging_since = *self.lagging_since.get_or_insert(started);
+        self.lagging_ticks += 1;
+        (self.lagging_ticks == self.lag_ticks).then(|| LagReport {
+            average_tick_duration,
+            tick_interval: self.tick_interval,
+            lagging_for: lagging_since.elapsed(),
+            lagging_ticks: self.lagging_ticks,
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::OrderByError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Oldest or smallest first.
+    Asc,
+    /// Newest or largest first.
+    Desc,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Asc => write!(f, "ASC"),
+            Self::Desc => write!(f, "DESC"),
+        }
+    }
+}
+
+/// Order a query's rows are processed in, see `PgDbAgentQueryActionParams::with_order_by`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBy {
+    /// One of the query's result columns.
+    pub column: String,
+    pub direction: Direction,
+}
+
+impl OrderBy {
+    pub fn new(column: impl Into<String>, direction: Direction) -> Self {
+        Self {
+            column: column.into(),
+            direction,
+        }
+    }
+
+    /// Oldest first by `column`, e.g. a creation timestamp.
+    pub fn asc(column: impl Into<String>) -> Self {
+        Self::new(column, Direction::Asc)
+    }
+
+    /// Newest first by `column`.
+    pub fn desc(column: impl Into<String>) -> Self {
+        Self::new(column, Direction::Desc)
+    }
+
+    /// The `ORDER BY` clause, the column quoted so it can't be anything but an identifier.
+    fn clause(&self) -> String {
+        format!("ORDER BY \"{}\" {}", self.column.replace('"', "\"\""), self.direction)
+    }
+}
+
+/// A query's current `OrderBy` and the columns it may be switched to, shared with `AgentHandle`.
+pub(crate) struct Ordering {
+    allowed_columns: Vec<String>,
+    current: Mutex<OrderBy>,
+}
+
+impl Ordering {
+    pub(crate) fn new(order_by: OrderBy, allowed_columns: Vec<String>) -> Self {
+        Self {
+            allowed_columns,
+            current: Mutex::new(order_by),
+        }
+    }
+
+    pub(crate) fn is_allowed(&self, order_by: &OrderBy) -> bool {
+        self.allowed_columns.contains(&order_by.column)
+    }
+
+    pub(crate) fn current(&self) -> OrderBy {
+        self.current.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Switches to `order_by`. `keyset` queries, with a `cursor_bind`, keep theirs: their keyset condition only works in
+    /// the order it was written for.
+    pub(crate) fn set(&self, query: &str, order_by: OrderBy, keyset: bool) -> Result<(), OrderByError> {
+        if !self.is_allowed(&order_by) {
+            return Err(OrderByError::ColumnNotAllowed {
+                query: query.to_string(),
+                column: order_by.column,
+            });
+        }
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        if keyset && *current != order_by {
+            return Err(OrderByError::KeysetQuery(query.to_string()));
+        }
+        *current = order_by;
+        Ok(())
+    }
+}
+
+/// The SQL sent to the database for `query`: as is, or wrapped to sort it and cap it at `limit` rows. The sort
+/// comes before the limit, so a capped run gets the first rows in that order.
+pub(crate) fn statement<'a>(query: &'a str, ordering: Option<&Ordering>, limit: Option<usize>) -> Cow<'a, str> {
+    if ordering.is_none() && limit.is_none() {
+        return Cow::Borrowed(query);
+    }
+    let mut statement = format!("SELECT * FROM ({}) AS sub", query.trim_end().trim_end_matches(';'));
+    if let Some(ordering) = ordering {
+        statement.push(' ');
+        statement.push_str(&ordering.current().clause());
+    }
+    if let Some(limit) = limit {
+        statement.push_str(&format!(" LIMIT {limit}"));
+    }
+    Cow::Owned(statement)
+}
@@ -0,0 +1,19 @@
+/// What a query does when its actions from the previous run are still going at its next run.
+///
+/// Setting a policy makes the query's actions run in their own task, so the poll loop moves on to the
+/// next query as soon as the rows are fetched. Without one the loop waits for every action inline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// Wait for the previous run's actions to finish, then fetch and process the new rows.
+    Queue,
+    /// Leave the previous run alone and skip this run, the query stays due and is retried on the next tick.
+    Skip,
+    /// Abort the previous run and start over with fresh rows. The abort takes effect between rows,
+    /// an action call that already started is never interrupted.
+    Cancel,
+    /// Fetch the new rows while the previous run's actions are still going, then wait for them to finish before
+    /// processing the new rows, so fetching overlaps with processing but runs still never do. Only for queries with a
+    /// `cursor_bind`, which fetch past the previous run's rows, `PgDbAgentParams::new` fails with
+    /// `ParamsError::PrefetchWithoutCursor` otherwise: a query for unprocessed rows would fetch them again.
+    Prefetch,
+}
@@ -0,0 +1,45 @@
+use sqlx::{postgres::PgRow, PgPool};
+use tokio::sync::broadcast;
+
+/// Fans every row `query` returns out to all subscribers of a `tokio::sync::broadcast` channel.
+///
+/// The agent never waits for subscribers: a subscriber that falls behind gets `RecvError::Lagged` on its side,
+/// and when nobody is subscribed the tick's rows are dropped with a warning instead of failing the tick.
+pub struct PgDbAgentBroadcastActionParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub pool: PgPool,
+    pub query: String,
+    pub sender: broadcast::Sender<T>,
+}
+
+impl<T> PgDbAgentBroadcastActionParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Clone + Send + Sync + Unpin + 'static,
+{
+    pub fn new(pool: PgPool, query: String, sender: broadcast::Sender<T>) -> Self {
+        Self { pool, query, sender }
+    }
+}
+
+impl<T> PgDbAgentBroadcastActionParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub(crate) async fn process(&self, pool: &PgPool, persistent: bool) -> Result<(), sqlx::Error> {
+        let rows: Vec<T> = crate::unprepared::fetch_all(pool, &self.query, persistent).await?;
+        let count = rows.len();
+        for row in rows {
+            if self.sender.send(row).is_err() {
+                log::warn!(
+                    "No subscribers for `{}`, dropped {} row(s) of this tick",
+                    self.query,
+                    count
+                );
+                break;
+            }
+        }
+        Ok(())
+    }
+}
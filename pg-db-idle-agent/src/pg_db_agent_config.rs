@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use sqlx::{postgres::PgRow, PgPool};
+
+use crate::{
+    ConfigError, PgDbAgentNotifyParams, PgDbAgentParams, PgDbAgentQueryActionParams,
+};
+
+pub type BoxedAction<T> = Box<dyn Fn(&T) + Send + Sync>;
+type SharedAction<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// Declarative agent configuration, e.g. loaded from a YAML or TOML file.
+/// Actions can't be serialized, so every query names an action registered in a `PgDbAgentActionRegistry`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PgDbAgentConfig {
+    pub interval_ms: u64,
+    pub queries: Vec<PgDbAgentQueryConfig>,
+    pub max_consecutive_errors: Option<u32>,
+    pub notify: Option<PgDbAgentNotifyConfig>,
+    #[cfg(feature = "cron")]
+    pub cron: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PgDbAgentQueryConfig {
+    pub query: String,
+    pub name: Option<String>,
+    /// Name the action was registered under.
+    pub action: String,
+    pub interval_ms: Option<u64>,
+    #[serde(default)]
+    pub blocking_action: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PgDbAgentNotifyConfig {
+    pub channels: Vec<String>,
+    pub debounce_ms: u64,
+    pub debounce_max_wait_ms: Option<u64>,
+}
+
+/// Named actions that a `PgDbAgentConfig` can refer to.
+pub struct PgDbAgentActionRegistry<T> {
+    actions: HashMap<String, SharedAction<T>>,
+}
+
+impl<T> Default for PgDbAgentActionRegistry<T> {
+    fn default() -> Self {
+        Self {
+            actions: HashMap::new(),
+        }
+    }
+}
+
+impl<T> PgDbAgentActionRegistry<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `action` under `name`, replacing any action previously registered under the same name.
+    pub fn register_action<F>(&mut self, name: impl Into<String>, action: F) -> &mut Self
+    where
+        F: Fn(&T) + Send + Sync + 'static,
+    {
+        self.actions.insert(name.into(), Arc::new(action));
+        self
+    }
+
+    fn action(&self, name: &str) -> Result<BoxedAction<T>, ConfigError> {
+        let action = self
+            .actions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ConfigError::UnknownAction(name.to_string()))?;
+        Ok(Box::new(move |row: &T| action(row)))
+    }
+}
+
+impl PgDbAgentConfig {
+    /// Builds agent params running every configured query against `pool`.
+    pub fn into_params<T, E>(
+        self,
+        pool: PgPool,
+        registry: &PgDbAgentActionRegistry<T>,
+        error_handler: E,
+    ) -> Result<PgDbAgentParams<T, BoxedAction<T>, E>, ConfigError>
+    where
+        T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+        E: Fn(sqlx::Error) + Send + Sync + 'static,
+    {
+        let query_actions = self
+            .queries
+            .into_iter()
+            .map(|query| {
+                let action = registry.action(&query.action)?;
+                let mut query_action = PgDbAgentQueryActionParams::new(pool.clone(), query.query, action)
+                    .with_blocking_action(query.blocking_action);
+                if let Some(name) = query.name {
+                    query_action = query_action.with_name(name);
+                }
+                if let Some(interval_ms) = query.interval_ms {
+                    query_action = query_action.with_interval(Duration::from_millis(interval_ms));
+                }
+                Ok(query_action)
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+
+        let mut params = PgDbAgentParams::new(query_actions, Duration::from_millis(self.interval_ms), error_handler)?;
+        if let Some(max_consecutive_errors) = self.max_consecutive_errors {
+            params = params.with_max_consecutive_errors(max_consecutive_errors);
+        }
+        if let Some(notify) = self.notify {
+            let mut notify_params = PgDbAgentNotifyParams::new(
+                pool.clone(),
+                notify.channels,
+                Duration::from_millis(notify.debounce_ms),
+            );
+            if let Some(debounce_max_wait_ms) = notify.debounce_max_wait_ms {
+                notify_params = notify_params.with_debounce_max_wait(Duration::from_millis(debounce_max_wait_ms));
+            }
+            params = params.with_notify(notify_params)?;
+        }
+        #[cfg(feature = "cron")]
+        if let Some(cron) = self.cron {
+            params = params.with_schedule(crate::Schedule::Cron(cron));
+        }
+        Ok(params)
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use sqlx::{postgres::PgRow, PgPool};
+
+use crate::{audit::Audit, ActionError, ActionErrorHandler, AuditId, AuditOutcome, AuditSink, RowHandler};
+
+/// Runs `query` on every tick and awaits `handler` for each row in order.
+/// A row whose handler fails is reported to `on_handler_error` and doesn't stop the remaining rows.
+pub struct PgDbAgentHandlerParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub pool: PgPool,
+    pub query: String,
+    pub handler: Box<dyn RowHandler<T>>,
+    pub on_handler_error: Option<ActionErrorHandler<T>>,
+    pub audit_id: Option<AuditId<T>>,
+}
+
+impl<T> PgDbAgentHandlerParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub fn new<H>(pool: PgPool, query: String, handler: H) -> Self
+    where
+        H: RowHandler<T>,
+    {
+        Self {
+            pool,
+            query,
+            handler: Box::new(handler),
+            on_handler_error: None,
+            audit_id: None,
+        }
+    }
+
+    /// Called for every row whose handler returned an error.
+    pub fn with_handler_error_handler<E>(mut self, on_handler_error: E) -> Self
+    where
+        E: Fn(&T, ActionError) + Send + Sync + 'static,
+    {
+        self.on_handler_error = Some(Box::new(on_handler_error));
+        self
+    }
+
+    /// Identify each row in the events sent to the agent's `with_audit_sink`, e.g. by its primary key.
+    pub fn with_audit_id<I>(mut self, audit_id: I) -> Self
+    where
+        I: Fn(&T) -> String + Send + Sync + 'static,
+    {
+        self.audit_id = Some(Arc::new(audit_id));
+        self
+    }
+
+    pub(crate) async fn process(
+        &self,
+        pool: &PgPool,
+        persistent: bool,
+        audit_sink: Option<&Arc<dyn AuditSink>>,
+    ) -> Result<(), sqlx::Error> {
+        let rows: Vec<T> = crate::unprepared::fetch_all(pool, &self.query, persistent).await?;
+        let audit = audit_sink.map(|sink| Audit {
+            sink: Arc::clone(sink),
+            query: self.query.clone(),
+            id: self.audit_id.clone(),
+        });
+        for row in &rows {
+            let result = self.handler.handle(row).await;
+            if let Some(audit) = &audit {
+                let outcome = match &result {
+                    Ok(()) => AuditOutcome::Success,
+                    Err(e) => AuditOutcome::Failure(e.to_string()),
+                };
+                audit.record(audit.row_id(row), 0, outcome).await;
+            }
+            if let Err(e) = result {
+                if let Some(on_handler_error) = &self.on_handler_error {
+                    on_handler_error(row, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+
+use sqlx::{postgres::PgRow, PgPool};
+
+use crate::ActionError;
+
+pub type FallibleAction<T> = Box<dyn Fn(&T) -> Result<(), ActionError> + Send + Sync>;
+pub type ActionErrorHandler<T> = Box<dyn Fn(&T, ActionError) + Send + Sync>;
+#[cfg(feature = "serde")]
+pub type DeadLetterPayload<T> = Box<dyn Fn(&T, &ActionError) -> serde_json::Value + Send + Sync>;
+
+/// Rows whose failed attempts are counted at most, past that the rows that failed longest ago start over.
+const MAX_TRACKED_ROWS: usize = 10_000;
+
+/// "Process then delete" helper for outbox tables.
+///
+/// Each tick it opens a transaction, runs `query`, invokes `action` for every row and deletes
+/// the rows whose action succeeded with `DELETE FROM <table> WHERE id = ANY($1)` before committing.
+/// Rows whose action failed stay in the table and are picked up again on a later tick.
+///
+/// The query should lock what it selects (`FOR UPDATE SKIP LOCKED`) so concurrent agents don't process the same rows.
+/// `table` is interpolated into the DELETE statement as is, so it must come from trusted configuration.
+/// With `with_dead_letter` a row that keeps failing is eventually moved to a dead-letter table instead.
+pub struct PgDbAgentOutboxParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub pool: PgPool,
+    pub query: String,
+    pub table: String,
+    pub id_extractor: Box<dyn Fn(&T) -> i64 + Send + Sync>,
+    pub action: FallibleAction<T>,
+    pub on_action_error: Option<ActionErrorHandler<T>>,
+    pub dead_letter: Option<DeadLetterConfig>,
+    #[cfg(feature = "serde")]
+    pub dead_letter_payload: Option<DeadLetterPayload<T>>,
+    /// Failed attempts per row id, only kept with a dead-letter table.
+    failures: Mutex<Failures>,
+}
+
+/// Failed attempts per row id, each with the number of the failure that was counted last for it.
+#[derive(Default)]
+struct Failures {
+    attempts: HashMap<i64, (u32, u64)>,
+    failed: u64,
+}
+
+impl Failures {
+    /// Counts a failed attempt for `id` and returns how many there were, forgetting the rows that failed longest ago
+    /// once more than `MAX_TRACKED_ROWS` are counted, e.g. rows another agent processed or that were deleted.
+    fn record(&mut self, id: i64) -> u32 {
+        self.failed += 1;
+        let failed = self.failed;
+        let (attempts, last) = self.attempts.entry(id).or_default();
+        *attempts += 1;
+        *last = failed;
+        let attempts = *attempts;
+        if self.attempts.len() > MAX_TRACKED_ROWS {
+            let oldest = failed - (MAX_TRACKED_ROWS / 2) as u64;
+            self.attempts.retain(|_, (_, last)| *last > oldest);
+        }
+        attempts
+    }
+}
+
+/// Where an outbox moves rows whose action failed `max_attempts` times in a row, see `PgDbAgentOutboxParams::with_dead_letter`.
+///
+/// The row is deleted from the outbox and `INSERT INTO <table> (id, error) VALUES ($1, $2)` records its id and the
+/// last error in the same transaction, so `table` needs an `id BIGINT` and an `error TEXT` column (other columns,
+/// e.g. a `failed_at` timestamp, need defaults). Like the outbox's own table, it must come from trusted configuration.
+/// With the `serde` feature, `PgDbAgentOutboxParams::with_dead_letter_payload` adds a `payload JSONB` column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetterConfig {
+    pub table: String,
+    pub max_attempts: u32,
+}
+
+impl DeadLetterConfig {
+    pub fn new(table: String, max_attempts: u32) -> Self {
+        Self { table, max_attempts }
+    }
+}
+
+impl<T> PgDbAgentOutboxParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub fn new<I, F>(pool: PgPool, query: String, table: String, id_extractor: I, action: F) -> Self
+    where
+        I: Fn(&T) -> i64 + Send + Sync + 'static,
+        F: Fn(&T) -> Result<(), ActionError> + Send + Sync + 'static,
+    {
+        Self {
+            pool,
+            query,
+            table,
+            id_extractor: Box::new(id_extractor),
+            action: Box::new(action),
+            on_action_error: None,
+            dead_letter: None,
+            #[cfg(feature = "serde")]
+            dead_letter_payload: None,
+            failures: Mutex::default(),
+        }
+    }
+
+    /// Move rows whose action keeps failing to a dead-letter table so they stop being retried on every tick.
+    /// Attempts are counted in memory, so they start over when the agent restarts, and for at most 10 000 rows at a
+    /// time, past that the rows that failed longest ago start over too.
+    pub fn with_dead_letter(mut self, dead_letter: DeadLetterConfig) -> Self {
+        self.dead_letter = Some(dead_letter);
+        self
+    }
+
+    /// Also store a payload built from the row and its last error with every dead-lettered row, e.g. the fields needed
+    /// to reprocess it, so dead-letter rows describe themselves. The dead-letter table then needs a `payload JSONB`
+    /// column as well, filled by `INSERT INTO <table> (id, error, payload) VALUES ($1, $2, $3)`.
+    #[cfg(feature = "serde")]
+    pub fn with_dead_letter_payload<S>(mut self, serialize: S) -> Self
+    where
+        S: Fn(&T, &ActionError) -> serde_json::Value + Send + Sync + 'static,
+    {
+        self.dead_letter_payload = Some(Box::new(serialize));
+        self
+    }
+
+    /// Called for every row whose action failed, the row itself is left in the outbox.
+    pub fn with_action_error_handler<H>(mut self, on_action_error: H) -> Self
+    where
+        H: Fn(&T, ActionError) + Send + Sync + 'static,
+    {
+        self.on_action_error = Some(Box::new(on_action_error));
+        self
+    }
+
+    pub(crate) async fn process(&self, pool: &PgPool, persistent: bool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let rows: Vec<T> = crate::unprepared::fetch_all(&mut *tx, &self.query, persistent).await?;
+
+        let mut processed_ids = Vec::with_capacity(rows.len());
+        let mut dead_letters = Vec::new();
+        for row in &rows {
+            let id = (self.id_extractor)(row);
+            match (self.action)(row) {
+                Ok(()) => processed_ids.push(id),
+                Err(e) => {
+                    if self.exhausted(id) {
+                        dead_letters.push((id, e.to_string(), self.payload(row, &e)));
+                        processed_ids.push(id);
+                    }
+                    if let Some(on_action_error) = &self.on_action_error {
+                        on_action_error(row, e);
+                    }
+                }
+            }
+        }
+
+        if let Some(dead_letter) = &self.dead_letter {
+            for (id, error, payload) in dead_letters {
+                let statement = match payload {
+                    Some(_) => format!(
+                        "INSERT INTO {} (id, error, payload) VALUES ($1, $2, $3::jsonb)",
+                        dead_letter.table
+                    ),
+                    None => format!("INSERT INTO {} (id, error) VALUES ($1, $2)", dead_letter.table),
+                };
+                let mut query = sqlx::query(&statement).bind(id).bind(error);
+                if let Some(payload) = payload {
+                    query = query.bind(payload);
+                }
+                query
+                    .persistent(persistent)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        if processed_ids.is_empty() {
+            return tx.commit().await;
+        }
+        sqlx::query(&format!("DELETE FROM {} WHERE id = ANY($1)", self.table))
+            .bind(&processed_ids)
+            .persistent(persistent)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        // Only now are the rows gone, a failed commit leaves them in the outbox with their attempts.
+        if self.dead_letter.is_some() {
+            let mut failures = self.failures();
+            for id in processed_ids {
+                failures.attempts.remove(&id);
+            }
+        }
+        Ok(())
+    }
+
+    /// The `with_dead_letter_payload` payload of a dead-lettered row, as JSON text.
+    #[cfg(feature = "serde")]
+    fn payload(&self, row: &T, error: &ActionError) -> Option<String> {
+        self.dead_letter_payload
+            .as_ref()
+            .map(|serialize| serialize(row, error).to_string())
+    }
+
+    #[cfg(not(feature = "serde"))]
+    fn payload(&self, _row: &T, _error: &ActionError) -> Option<String> {
+        None
+    }
+
+    fn failures(&self) -> std::sync::MutexGuard<'_, Failures> {
+        self.failures.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Counts a failed attempt for `id`, `true` once it reached the dead-letter table's `max_attempts`.
+    fn exhausted(&self, id: i64) -> bool {
+        let Some(dead_letter) = &self.dead_letter else {
+            return false;
+        };
+        self.failures().record(id) >= dead_letter.max_attempts
+    }
+}
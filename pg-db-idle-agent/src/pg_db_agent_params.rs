@@ -1,58 +1,1282 @@
-use std::{marker::PhantomData, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    future::Future,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
 
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
 use sqlx::{postgres::PgRow, PgPool};
+use tokio::time::MissedTickBehavior;
 
+use crate::{
+    accumulator::Accumulator, default_is_retryable, order_by::{self, Ordering}, OrderBy, ActionTimings, AuditId, AuditSink, expected_column, row_diff::DiffRows, AgentSummary, BindValue, CredentialProvider, DuplicatePolicy, ExpectedColumn, GlobalConcurrencyLimiter, LagReport, OverlapPolicy, ParamsError, PgDbAgentBroadcastActionParams, PoolClosedPolicy,
+    PgDbAgentHandlerParams, PgDbAgentOutboxParams, PgDbAgentShardedActionParams, PgDbAgentRawActionParams, PgDbAgentSinkParams, PgDbAgentTenantActionParams, ErrorBackoff, IsRetryable, RetryPolicy, RowAction, RowDiff, Schedule, Scheduler, SizeHint, StandbyPolicy,
+    StopReason, WriteBack,
+};
 
 
 
+
+pub type CursorBind<T> = Box<dyn Fn(Option<&T>) -> BindValue + Send + Sync>;
+pub type PartitionKey<T> = Arc<dyn Fn(&T) -> u64 + Send + Sync>;
+pub type RowFinalizer<T> = Arc<dyn Fn(&T) + Send + Sync>;
+pub type Dedup<T> = Box<dyn Fn(Vec<T>) -> Vec<T> + Send + Sync>;
+pub type DedupSort<T> = Box<dyn Fn(&T, &T) -> std::cmp::Ordering + Send + Sync>;
+pub type SlowQueryHook = Box<dyn Fn(&str, Duration) + Send + Sync>;
+pub type ActionTimingsHook = Box<dyn Fn(&ActionTimings) + Send + Sync>;
+pub type DriftHook = Box<dyn Fn(Duration) + Send + Sync>;
+pub type CursorHook = Box<dyn Fn(&str, BindValue) + Send + Sync>;
+pub type ShouldTick = Box<dyn Fn() -> BoxFuture<'static, bool> + Send + Sync>;
+
 pub struct PgDbAgentQueryActionParams<T, F>
 where
     T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
-    F: Fn(&T) + Send + Sync + 'static,
+    F: RowAction<T>,
 {
     pub pool: PgPool,
+    pub write_pool: Option<PgPool>,
     pub query: String,
-    pub action: F,
+    pub name: Option<String>,
+    pub action: Arc<F>,
+    pub interval: Option<Duration>,
+    pub auto_limit: Option<usize>,
+    pub blocking_action: bool,
+    pub overlap_policy: Option<OverlapPolicy>,
+    pub on_decode_error: Option<Box<dyn Fn(sqlx::Error) + Send + Sync>>,
+    pub on_schema_drift: Option<Box<dyn Fn(sqlx::Error) + Send + Sync>>,
+    pub pause_on_schema_drift: bool,
+    pub cursor_bind: Option<CursorBind<T>>,
+    pub before_query: Vec<String>,
+    pub after_query: Vec<String>,
+    /// Names of the queries this one runs after within a tick, see `with_run_after`.
+    pub run_after: Vec<String>,
+    pub application_name: Option<String>,
+    pub audit_id: Option<AuditId<T>>,
+    pub write_back: Option<Arc<WriteBack<T>>>,
+    pub on_became_empty: Option<Box<dyn Fn() + Send + Sync>>,
+    pub on_became_nonempty: Option<Box<dyn Fn() + Send + Sync>>,
+    pub on_empty: Option<Box<dyn Fn() + Send + Sync>>,
+    pub on_recovered: Option<Box<dyn Fn(Duration) + Send + Sync>>,
+    pub size_of: Option<fn(&T) -> usize>,
+    pub slow_query_threshold: Option<Duration>,
+    pub on_slow_query: Option<SlowQueryHook>,
+    pub priority: u8,
+    pub partition_key: Option<PartitionKey<T>>,
+    pub dedup: Option<Dedup<T>>,
+    pub dedup_sort: Option<DedupSort<T>>,
+    pub on_stop: Option<Box<dyn Fn() + Send + Sync>>,
+    pub expected_columns: Vec<ExpectedColumn>,
+    pub expected_max: Option<usize>,
+    pub on_unexpected_volume: Option<Box<dyn Fn(usize) + Send + Sync>>,
+    pub limit_on_unexpected_volume: bool,
+    /// `expected_max`, from a run that exceeded it with `limit_on_unexpected_volume` set until one that came in under.
+    pub(crate) volume_limit: Mutex<Option<usize>>,
+    /// Which row of a key `dedup` keeps.
+    pub(crate) dedup_policy: DuplicatePolicy,
+    pub(crate) diff: Option<Box<dyn DiffRows<T>>>,
+    pub(crate) ordering: Option<Arc<Ordering>>,
     pub _marker: PhantomData<T>, // Add this so compile does not complain about unused parameter T.
 }
 
 impl<T, F> PgDbAgentQueryActionParams<T, F>
 where
     T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
-    F: Fn(&T) + Send + Sync + 'static,
+    F: RowAction<T>,
 {
     pub fn new(pool: PgPool, query: String, action: F) -> Self {
         Self {
             pool,
+            write_pool: None,
             query,
-            action,
+            name: None,
+            action: Arc::new(action),
+            interval: None,
+            auto_limit: None,
+            blocking_action: false,
+            overlap_policy: None,
+            on_decode_error: None,
+            on_schema_drift: None,
+            pause_on_schema_drift: false,
+            cursor_bind: None,
+            before_query: Vec::new(),
+            after_query: Vec::new(),
+            run_after: Vec::new(),
+            application_name: None,
+            audit_id: None,
+            write_back: None,
+            on_became_empty: None,
+            on_became_nonempty: None,
+            on_empty: None,
+            on_recovered: None,
+            size_of: None,
+            slow_query_threshold: None,
+            on_slow_query: None,
+            priority: 0,
+            partition_key: None,
+            dedup: None,
+            dedup_sort: None,
+            on_stop: None,
+            expected_columns: Vec::new(),
+            expected_max: None,
+            on_unexpected_volume: None,
+            limit_on_unexpected_volume: false,
+            volume_limit: Mutex::new(None),
+            dedup_policy: DuplicatePolicy::default(),
+            diff: None,
+            ordering: None,
             _marker: PhantomData,
         }
     }
+
+    /// Short name for the query, used in tracing spans and to address it through `AgentHandle::status_for`
+    /// and `AgentHandle::set_query_enabled`.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Run this query after the query named `name` (see `with_name`) within every tick, e.g. when it processes what
+    /// that query's action produced. Call it again to run after several queries. Takes precedence over priorities, and
+    /// runs of `name` aren't overlapped: they finish before this query runs, whatever its `OverlapPolicy`. With
+    /// `PgDbAgentParams::with_time_budget_per_tick` this query isn't held back once `name` ran in the tick.
+    /// `PgDbAgentParams::new` fails if no query is named `name` or the queries run after each other in a cycle.
+    pub fn with_run_after(mut self, name: impl Into<String>) -> Self {
+        self.run_after.push(name.into());
+        self
+    }
+
+    /// Queries with a higher priority run first within a tick, ties keep the order they were configured in.
+    /// See `PgDbAgentParams::with_strict_priority` to also hold lower priorities back. Defaults to 0.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Columns `T`'s `FromRow` reads, checked against the query's result columns by
+    /// `PgDbAgentParams::validate_columns` so a query or struct that drifted fails before the loop starts
+    /// rather than with decode errors on every tick.
+    pub fn with_expected_columns<I, C>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = C>,
+        C: Into<ExpectedColumn>,
+    {
+        self.expected_columns = columns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Poll this query at its own interval instead of the agent's `interval_secs`.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Cap every run at `auto_limit` rows by running the query as `SELECT * FROM (<query>) AS sub LIMIT <auto_limit>`.
+    /// The query must therefore be valid as a subquery, i.e. a single `SELECT` (or `VALUES`/`WITH ... SELECT`)
+    /// without `RETURNING` or data-modifying statements.
+    pub fn with_auto_limit(mut self, auto_limit: usize) -> Self {
+        self.auto_limit = Some(auto_limit);
+        self
+    }
+
+    /// Call `on_unexpected_volume` with the row count of every run that returned more than `expected_max` rows,
+    /// counted before dedup, e.g. to alert on a backlog or a query that suddenly matches the whole table.
+    pub fn with_expected_max<H>(mut self, expected_max: usize, on_unexpected_volume: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        self.expected_max = Some(expected_max);
+        self.on_unexpected_volume = Some(Box::new(on_unexpected_volume));
+        self
+    }
+
+    /// Once a run exceeded `with_expected_max`, cap the query at `expected_max` rows from the next run on, as
+    /// `with_auto_limit` would, until a run returns fewer rows than that, i.e. the backlog is gone. The run that
+    /// exceeded it is processed in full.
+    pub fn with_limit_on_unexpected_volume(mut self, limit_on_unexpected_volume: bool) -> Self {
+        self.limit_on_unexpected_volume = limit_on_unexpected_volume;
+        self
+    }
+
+    /// Keep reading from `pool` (e.g. a read replica) but hand `write_pool` (e.g. the primary) to actions
+    /// through `RowContext::write_pool` for their follow-up writes.
+    pub fn with_write_pool(mut self, write_pool: PgPool) -> Self {
+        self.write_pool = Some(write_pool);
+        self
+    }
+
+    /// Run the action for each row on Tokio's blocking thread pool via `spawn_blocking`, awaiting it before the next row.
+    /// Use this for CPU-heavy actions (serialization, compression) that would otherwise stall the async runtime.
+    /// Only applies to the synchronous `action`, rows are still processed one at a time and in order.
+    pub fn with_blocking_action(mut self, blocking_action: bool) -> Self {
+        self.blocking_action = blocking_action;
+        self
+    }
+
+    /// Run this query's actions in their own task and decide with `overlap_policy` what happens when they are
+    /// still running at the query's next run. Actions left running when the agent stops are not waited for.
+    pub fn with_overlap_policy(mut self, overlap_policy: OverlapPolicy) -> Self {
+        self.overlap_policy = Some(overlap_policy);
+        self
+    }
+
+    /// Decode rows one at a time and report rows that fail to decode into `T` (type mismatch, unexpected NULL)
+    /// to `on_decode_error` while still running the action for every row that decoded fine.
+    /// Errors of the query itself keep going to the agent's error handler.
+    pub fn with_decode_error_handler<D>(mut self, on_decode_error: D) -> Self
+    where
+        D: Fn(sqlx::Error) + Send + Sync + 'static,
+    {
+        self.on_decode_error = Some(Box::new(on_decode_error));
+        self
+    }
+
+    /// Hand runs failing because the query no longer returns the columns `T` decodes (missing, retyped or dropped
+    /// columns, e.g. mid-migration) to `on_schema_drift` instead of the error handler. The tick goes on with the other
+    /// queries, so a drifted query doesn't count towards `max_consecutive_errors`.
+    pub fn with_on_schema_drift<D>(mut self, on_schema_drift: D) -> Self
+    where
+        D: Fn(sqlx::Error) + Send + Sync + 'static,
+    {
+        self.on_schema_drift = Some(Box::new(on_schema_drift));
+        self
+    }
+
+    /// Disable the query after `on_schema_drift` was called instead of failing the same way on every run, until it's
+    /// enabled again with `AgentHandle::set_query_enabled`, which needs it to have a name.
+    pub fn with_pause_on_schema_drift(mut self, pause_on_schema_drift: bool) -> Self {
+        self.pause_on_schema_drift = pause_on_schema_drift;
+        self
+    }
+
+    /// Keyset style incremental polling: bind `cursor_bind(last)` to the query's `$1` on every run, where `last` is the
+    /// last row fetched so far (e.g. `WHERE id > $1 ORDER BY id`). `None` until a run returned rows, so the first
+    /// run binds `cursor_bind(None)`, and runs without rows keep the previous cursor.
+    pub fn with_cursor_bind<C>(mut self, cursor_bind: C) -> Self
+    where
+        C: Fn(Option<&T>) -> BindValue + Send + Sync + 'static,
+    {
+        self.cursor_bind = Some(Box::new(cursor_bind));
+        self
+    }
+
+    /// Statements run right before and right after the query, all inside one transaction on the same connection.
+    /// Use `SET LOCAL` (not `SET`) and `ON COMMIT DROP` temp tables so session state doesn't leak back into the pool.
+    /// If any statement fails the error goes to the error handler and the query is skipped for that tick.
+    pub fn with_query_hooks(mut self, before_query: Vec<String>, after_query: Vec<String>) -> Self {
+        self.before_query = before_query;
+        self.after_query = after_query;
+        self
+    }
+
+    /// Show the query's runs under `application_name` in `pg_stat_activity` and the server log, to tell which query
+    /// causes load. Set with `SET LOCAL` in a transaction around the query, like `with_query_hooks` statements, so the
+    /// pooled connection goes back with its own name and PgBouncer in transaction mode is fine with it. A pool used
+    /// by this query only can get the name from its `PgConnectOptions::application_name` instead, without the transaction.
+    pub fn with_application_name(mut self, application_name: impl Into<String>) -> Self {
+        self.application_name = Some(application_name.into());
+        self
+    }
+
+    /// Identify each row in the events sent to the agent's `with_audit_sink`, e.g. by its primary key.
+    pub fn with_audit_id<I>(mut self, audit_id: I) -> Self
+    where
+        I: Fn(&T) -> String + Send + Sync + 'static,
+    {
+        self.audit_id = Some(Arc::new(audit_id));
+        self
+    }
+
+    /// Write the rows the action ran for back in batches with `write_back`, e.g. to mark them as sent, instead of a
+    /// write and commit per row in the action. Writes go to the `with_write_pool` if the query has one.
+    pub fn with_write_back(mut self, write_back: WriteBack<T>) -> Self {
+        self.write_back = Some(Arc::new(write_back));
+        self
+    }
+
+    /// Called once the query was retired with `AgentHandle::remove_query` and its last actions finished.
+    pub fn with_on_stop<H>(mut self, on_stop: H) -> Self
+    where
+        H: Fn() + Send + Sync + 'static,
+    {
+        self.on_stop = Some(Box::new(on_stop));
+        self
+    }
+
+    /// Called when the query returns no rows after having returned rows on its previous run, e.g. a queue drained.
+    pub fn with_on_became_empty<H>(mut self, on_became_empty: H) -> Self
+    where
+        H: Fn() + Send + Sync + 'static,
+    {
+        self.on_became_empty = Some(Box::new(on_became_empty));
+        self
+    }
+
+    /// Called when the query returns rows after having returned none on its previous run, e.g. a queue started filling.
+    pub fn with_on_became_nonempty<H>(mut self, on_became_nonempty: H) -> Self
+    where
+        H: Fn() + Send + Sync + 'static,
+    {
+        self.on_became_nonempty = Some(Box::new(on_became_nonempty));
+        self
+    }
+
+    /// Called on every run that returns no rows, unlike `with_on_became_empty` which only fires on the transition,
+    /// e.g. to log an idle queue or update an idle metric.
+    pub fn with_on_empty<H>(mut self, on_empty: H) -> Self
+    where
+        H: Fn() + Send + Sync + 'static,
+    {
+        self.on_empty = Some(Box::new(on_empty));
+        self
+    }
+
+    /// Called on the first successful run after one or more failed runs with how long the query had been failing,
+    /// from the start of its first failed run, e.g. to send a "recovered" alert or close an incident.
+    pub fn with_on_recovered<H>(mut self, on_recovered: H) -> Self
+    where
+        H: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_recovered = Some(Box::new(on_recovered));
+        self
+    }
+
+    /// Process a run's rows concurrently across keys but serially, in fetch order, within each key, e.g. with the
+    /// account id as key so one account's events never race each other. Each key's rows run on a thread of the
+    /// blocking pool (`blocking_action` is implied), with `start_local` the keys run one after the other.
+    /// Keys are hashed, so two keys colliding only means they share a queue. Rows still go through the rate limiter
+    /// one at a time, and `max_total_rows` stops every key after the rows that were running.
+    pub fn with_partition_key<K, P>(mut self, partition_key: P) -> Self
+    where
+        K: Hash,
+        P: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        self.partition_key = Some(Arc::new(move |row| {
+            let mut hasher = DefaultHasher::new();
+            partition_key(row).hash(&mut hasher);
+            hasher.finish()
+        }));
+        self
+    }
+
+    /// Drop rows whose `key` equals the key of an earlier row of the same run, so the first one in order wins.
+    /// Which row that is must not change between ticks, so the query needs an `ORDER BY` (on columns that make the
+    /// order unique) or a `with_dedup_sort`, `PgDbAgentParams::new` fails with `ParamsError::UnorderedDedup` otherwise.
+    pub fn with_dedup<K, D>(self, key: D) -> Self
+    where
+        K: Hash + Eq,
+        D: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        self.with_dedup_policy(key, DuplicatePolicy::KeepFirst)
+    }
+
+    /// Like `with_dedup`, but `policy` picks whether the first or the last row of each key is kept.
+    pub fn with_dedup_policy<K, D>(mut self, key: D, policy: DuplicatePolicy) -> Self
+    where
+        K: Hash + Eq,
+        D: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        self.dedup = Some(Box::new(move |mut rows| {
+            let mut seen = HashSet::with_capacity(rows.len());
+            if policy == DuplicatePolicy::KeepLast {
+                rows.reverse();
+            }
+            let mut rows: Vec<T> = rows.into_iter().filter(|row| seen.insert(key(row))).collect();
+            if policy == DuplicatePolicy::KeepLast {
+                rows.reverse();
+            }
+            rows
+        }));
+        self.dedup_policy = policy;
+        self
+    }
+
+    /// Sort every run's rows with `compare` (stable) before `with_dedup` picks the first row of each key,
+    /// for queries that can't have an `ORDER BY`.
+    pub fn with_dedup_sort<C>(mut self, compare: C) -> Self
+    where
+        C: Fn(&T, &T) -> std::cmp::Ordering + Send + Sync + 'static,
+    {
+        self.dedup_sort = Some(Box::new(compare));
+        self
+    }
+
+    /// Compare every run's rows with the previous run's and report added, changed and removed rows to `diff`'s hooks,
+    /// right after the fetch and before the action runs.
+    pub fn with_diff<K>(mut self, diff: RowDiff<T, K>) -> Self
+    where
+        T: PartialEq + Clone,
+        K: Hash + Eq + Send + 'static,
+    {
+        self.diff = Some(Box::new(diff));
+        self
+    }
+
+    /// Process the rows in `order_by`, e.g. newest first to serve recent work before a backlog, by running the query as
+    /// `SELECT * FROM (<query>) AS sub ORDER BY "<column>" <direction>`, before the `LIMIT` of `with_auto_limit` if it
+    /// has one. The column must be one of `allowed_columns`, which are also the only columns `AgentHandle::set_order_by`
+    /// can switch to at runtime, so a column name coming from outside can't inject SQL. With `with_cursor_bind` the
+    /// order is fixed, it must match the keyset condition (`id > $1` for `ASC`, `id < $1` for `DESC`) since the cursor
+    /// is the last row in that order.
+    pub fn with_order_by<I, C>(mut self, order_by: OrderBy, allowed_columns: I) -> Self
+    where
+        I: IntoIterator<Item = C>,
+        C: Into<String>,
+    {
+        let allowed_columns = allowed_columns.into_iter().map(Into::into).collect();
+        self.ordering = Some(Arc::new(Ordering::new(order_by, allowed_columns)));
+        self
+    }
+
+    /// Whether the query can run as part of a `with_batch_queries` batch, i.e. needs nothing but its statement.
+    pub(crate) fn batchable(&self) -> bool {
+        self.cursor_bind.is_none() && !self.in_transaction() && self.overlap_policy.is_none()
+    }
+
+    /// Whether the query runs in a transaction with statements around it.
+    pub(crate) fn in_transaction(&self) -> bool {
+        !self.before_query.is_empty() || !self.after_query.is_empty() || self.application_name.is_some()
+    }
+
+    /// `SET LOCAL` statement for `application_name`, quoted since it can't be a bind value.
+    pub(crate) fn set_application_name(&self) -> Option<String> {
+        let application_name = self.application_name.as_ref()?;
+        Some(format!("SET LOCAL application_name = '{}'", application_name.replace('\'', "''")))
+    }
+
+    /// Sorts and dedups a run's rows as configured.
+    pub(crate) fn dedup_rows(&self, mut rows: Vec<T>) -> Vec<T> {
+        let Some(dedup) = &self.dedup else {
+            return rows;
+        };
+        if let Some(dedup_sort) = &self.dedup_sort {
+            rows.sort_by(|a, b| dedup_sort(a, b));
+        }
+        dedup(rows)
+    }
+
+    /// Whether the query has an `ORDER BY` anywhere, a heuristic that doesn't parse the SQL.
+    fn has_order_by(&self) -> bool {
+        if self.ordering.is_some() {
+            return true;
+        }
+        let query = self.query.split_whitespace().collect::<Vec<_>>().join(" ");
+        query.to_ascii_uppercase().contains("ORDER BY")
+    }
+
+    /// Measure the approximate bytes every run fetched with `T`'s `SizeHint`, reported in `QueryStatus::last_bytes`
+    /// and summed up per tick for `PgDbAgentParams::with_on_tick_bytes`.
+    pub fn with_size_hint(mut self) -> Self
+    where
+        T: SizeHint,
+    {
+        self.size_of = Some(T::size_hint);
+        self
+    }
+
+    /// Called with the query's name (its SQL if it has none) and the fetch duration whenever a single run of the
+    /// query takes longer than `threshold`, e.g. to log it or count it per query.
+    pub fn with_slow_query_hook<H>(mut self, threshold: Duration, on_slow_query: H) -> Self
+    where
+        H: Fn(&str, Duration) + Send + Sync + 'static,
+    {
+        self.slow_query_threshold = Some(threshold);
+        self.on_slow_query = Some(Box::new(on_slow_query));
+        self
+    }
+
+    /// Rows a run is capped at, the lower of `auto_limit` and a limit imposed by `limit_on_unexpected_volume`.
+    pub(crate) fn limit(&self) -> Option<usize> {
+        match (self.auto_limit, self.volume_limit()) {
+            (Some(auto_limit), Some(volume_limit)) => Some(auto_limit.min(volume_limit)),
+            (auto_limit, volume_limit) => auto_limit.or(volume_limit),
+        }
+    }
+
+    fn volume_limit(&self) -> Option<usize> {
+        *self.volume_limit.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Checks a run's row count against `expected_max`.
+    pub(crate) fn check_volume(&self, rows: usize) {
+        let (Some(expected_max), Some(on_unexpected_volume)) = (self.expected_max, &self.on_unexpected_volume) else {
+            return;
+        };
+        if rows <= expected_max {
+            // A capped run that came back full may have left rows behind, one that didn't caught up.
+            if rows < expected_max {
+                *self.volume_limit.lock().unwrap_or_else(PoisonError::into_inner) = None;
+            }
+            return;
+        }
+        on_unexpected_volume(rows);
+        if self.limit_on_unexpected_volume {
+            *self.volume_limit.lock().unwrap_or_else(PoisonError::into_inner) = Some(expected_max);
+        }
+    }
+
+    /// The SQL actually sent to the database, i.e. `query` wrapped in its `ORDER BY` and `LIMIT` when it has them.
+    pub(crate) fn statement(&self) -> Cow<'_, str> {
+        order_by::statement(&self.query, self.ordering.as_deref(), self.limit())
+    }
 }
 
 
 
+/// Statement run once the agent's loop stopped, see `PgDbAgentParams::with_on_complete_query`.
+pub struct CompleteQuery {
+    pub pool: PgPool,
+    pub query: String,
+    pub on_result: Box<dyn Fn(Result<u64, sqlx::Error>) + Send + Sync>,
+}
+
+/// Ticks averaged to detect an agent that can't keep up with its interval, unless `with_on_sustained_lag` sets another.
+pub const DEFAULT_LAG_TICKS: u32 = 10;
+
 pub struct PgDbAgentParams<T, F, E>
 where
     T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
-    F: Fn(&T) + Send + Sync + 'static,
+    F: RowAction<T>,
 {
     pub query_actions: Vec<PgDbAgentQueryActionParams<T, F>>,
     pub interval_secs: Duration,
     pub error_handler: E,
+    pub notify: Option<PgDbAgentNotifyParams>,
+    pub outboxes: Vec<PgDbAgentOutboxParams<T>>,
+    pub broadcasts: Vec<PgDbAgentBroadcastActionParams<T>>,
+    pub shards: Vec<PgDbAgentShardedActionParams<T>>,
+    pub tenants: Vec<PgDbAgentTenantActionParams<T>>,
+    pub raws: Vec<PgDbAgentRawActionParams>,
+    pub handlers: Vec<PgDbAgentHandlerParams<T>>,
+    pub sinks: Vec<PgDbAgentSinkParams<T>>,
+    pub max_consecutive_errors: Option<u32>,
+    pub max_total_rows: Option<u64>,
+    pub max_buffered_rows: Option<usize>,
+    pub global_concurrency_limiter: Option<GlobalConcurrencyLimiter>,
+    pub max_ticks: Option<u64>,
+    pub should_tick: Option<ShouldTick>,
+    pub strict_priority: bool,
+    pub time_budget_per_tick: Option<Duration>,
+    pub batch_queries: bool,
+    pub pgbouncer_compatible: bool,
+    pub(crate) accumulator: Option<Accumulator<T>>,
+    pub acquire_retry: Option<RetryPolicy>,
+    pub error_backoff: Option<ErrorBackoff>,
+    pub is_retryable: IsRetryable,
+    pub credential_provider: Option<Arc<dyn CredentialProvider>>,
+    pub audit_sink: Option<Arc<dyn AuditSink>>,
+    pub on_row_finalize: Option<RowFinalizer<T>>,
+    pub recent_errors: usize,
+    pub pinned_pool: Option<PgPool>,
+    pub standby: Option<StandbyPolicy>,
+    pub pool_closed_policy: PoolClosedPolicy,
+    pub on_stop: Option<Box<dyn Fn(StopReason) + Send + Sync>>,
+    pub on_complete: Option<Box<dyn Fn(AgentSummary) + Send + Sync>>,
+    pub on_complete_query: Option<CompleteQuery>,
+    pub lag_ticks: u32,
+    pub on_sustained_lag: Option<Box<dyn Fn(LagReport) + Send + Sync>>,
+    pub on_drift_exceeded: Option<(Duration, DriftHook)>,
+    pub on_cursor_advance: Option<CursorHook>,
+    pub on_tick_bytes: Option<Box<dyn Fn(u64) + Send + Sync>>,
+    pub time_actions: bool,
+    pub on_action_timings: Option<ActionTimingsHook>,
+    pub schedule: Schedule,
+    pub scheduler: Option<Box<dyn Scheduler>>,
+    pub missed_tick_behavior: MissedTickBehavior,
+    pub max_catchup_ticks: Option<usize>,
+    #[cfg(feature = "cron")]
+    pub cron_timezone: chrono_tz::Tz,
+    #[cfg(feature = "governor")]
+    pub rate_limiter: Option<Arc<governor::DefaultDirectRateLimiter>>,
+    #[cfg(feature = "fault-injection")]
+    pub fault_injection: Option<crate::FaultConfig>,
+    #[cfg(feature = "serde")]
+    pub debug_sink: Option<crate::DebugSink>,
 }
 
 impl<T, F, E> PgDbAgentParams<T, F, E>
 where
     T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
-    F: Fn(&T) + Send + Sync + 'static,
+    F: RowAction<T>,
 {
-    pub fn new(query_actions: Vec<PgDbAgentQueryActionParams<T, F>>, interval_secs: Duration, error_handler: E) -> Self {
-        Self {
+    /// Fails if `interval_secs` or any query's interval override is zero, which would make the agent panic on `start`,
+    /// or if a query's params don't fit together, see `ParamsError`.
+    ///
+    /// Intervals have millisecond resolution, so sub-100ms polling works as long as a tick (queries and actions)
+    /// finishes within the interval. Missed ticks are caught up in a burst, so when ticks keep taking longer than the
+    /// interval the agent polls back to back and a warning is logged once the average over the last
+    /// `DEFAULT_LAG_TICKS` ticks exceeds the interval, see `with_on_sustained_lag` to react to it.
+    pub fn new(
+        query_actions: Vec<PgDbAgentQueryActionParams<T, F>>,
+        interval_secs: Duration,
+        error_handler: E,
+    ) -> Result<Self, ParamsError> {
+        if interval_secs.is_zero() {
+            return Err(ParamsError::ZeroInterval);
+        }
+        if let Some(query_action) = query_actions
+            .iter()
+            .find(|query_action| query_action.interval.is_some_and(|interval| interval.is_zero()))
+        {
+            return Err(ParamsError::ZeroQueryInterval {
+                query: query_action.query.clone(),
+            });
+        }
+        if let Some(query_action) = query_actions.iter().find(|query_action| {
+            query_action.dedup.is_some() && query_action.dedup_sort.is_none() && !query_action.has_order_by()
+        }) {
+            return Err(ParamsError::UnorderedDedup {
+                query: query_action.query.clone(),
+            });
+        }
+        if let Some(query_action) = query_actions.iter().find(|query_action| {
+            query_action.overlap_policy == Some(OverlapPolicy::Prefetch) && query_action.cursor_bind.is_none()
+        }) {
+            return Err(ParamsError::PrefetchWithoutCursor {
+                query: query_action.query.clone(),
+            });
+        }
+        crate::dependencies::run_order(&query_actions)?;
+        for query_action in &query_actions {
+            let Some(ordering) = &query_action.ordering else {
+                continue;
+            };
+            let order_by = ordering.current();
+            if !ordering.is_allowed(&order_by) {
+                return Err(ParamsError::OrderByNotAllowed {
+                    query: query_action.query.clone(),
+                    column: order_by.column,
+                });
+            }
+        }
+        Ok(Self {
             query_actions,
             interval_secs,
             error_handler,
+            notify: None,
+            outboxes: Vec::new(),
+            broadcasts: Vec::new(),
+            shards: Vec::new(),
+            tenants: Vec::new(),
+            raws: Vec::new(),
+            handlers: Vec::new(),
+            sinks: Vec::new(),
+            max_consecutive_errors: None,
+            max_total_rows: None,
+            max_buffered_rows: None,
+            global_concurrency_limiter: None,
+            max_ticks: None,
+            should_tick: None,
+            strict_priority: false,
+            time_budget_per_tick: None,
+            batch_queries: false,
+            pgbouncer_compatible: false,
+            accumulator: None,
+            acquire_retry: None,
+            error_backoff: None,
+            is_retryable: Box::new(default_is_retryable),
+            credential_provider: None,
+            audit_sink: None,
+            on_row_finalize: None,
+            recent_errors: 0,
+            pinned_pool: None,
+            standby: None,
+            pool_closed_policy: PoolClosedPolicy::default(),
+            on_stop: None,
+            on_complete: None,
+            on_complete_query: None,
+            lag_ticks: DEFAULT_LAG_TICKS,
+            on_sustained_lag: None,
+            on_drift_exceeded: None,
+            on_cursor_advance: None,
+            on_tick_bytes: None,
+            time_actions: false,
+            on_action_timings: None,
+            schedule: Schedule::Interval,
+            scheduler: None,
+            missed_tick_behavior: MissedTickBehavior::Burst,
+            max_catchup_ticks: None,
+            #[cfg(feature = "cron")]
+            cron_timezone: chrono_tz::Tz::UTC,
+            #[cfg(feature = "governor")]
+            rate_limiter: None,
+            #[cfg(feature = "fault-injection")]
+            fault_injection: None,
+            #[cfg(feature = "serde")]
+            debug_sink: None,
+        })
+    }
+
+    /// Also run a tick whenever a notification arrives on one of the configured channels.
+    /// The interval keeps ticking as usual, so NOTIFY only makes the agent react sooner. Fails with
+    /// `ParamsError::UnknownChannelQuery` if a channel is mapped to a name none of the queries has.
+    pub fn with_notify(mut self, notify: PgDbAgentNotifyParams) -> Result<Self, ParamsError> {
+        for (channel, names) in &notify.channel_queries {
+            let unknown = names
+                .iter()
+                .find(|name| !self.query_actions.iter().any(|query_action| query_action.name.as_ref() == Some(*name)));
+            if let Some(name) = unknown {
+                return Err(ParamsError::UnknownChannelQuery {
+                    channel: channel.clone(),
+                    name: name.clone(),
+                });
+            }
         }
+        self.notify = Some(notify);
+        Ok(self)
     }
-}
\ No newline at end of file
+
+    /// Register an outbox that is processed on every tick, after the query actions.
+    pub fn with_outbox(mut self, outbox: PgDbAgentOutboxParams<T>) -> Self {
+        self.outboxes.push(outbox);
+        self
+    }
+
+    /// Register a broadcast query whose rows are sent to every subscriber on every tick, after the outboxes.
+    pub fn with_broadcast(mut self, broadcast: PgDbAgentBroadcastActionParams<T>) -> Self {
+        self.broadcasts.push(broadcast);
+        self
+    }
+
+    /// Register a query that runs against several shards on every tick, after the broadcasts.
+    pub fn with_sharded<S>(mut self, sharded: PgDbAgentShardedActionParams<T, S>) -> Self
+    where
+        S: Fn(&T, usize) + Send + Sync + 'static,
+    {
+        self.shards.push(sharded.boxed());
+        self
+    }
+
+    /// Register a query that runs once per tenant on every tick, after the sharded queries and before the raw queries.
+    pub fn with_per_tenant<A>(mut self, per_tenant: PgDbAgentTenantActionParams<T, A>) -> Self
+    where
+        A: Fn(&T, &BindValue) + Send + Sync + 'static,
+    {
+        self.tenants.push(per_tenant.boxed());
+        self
+    }
+
+    /// Register a query whose rows are handed to its action undecoded on every tick, after the tenant queries and
+    /// before the handlers.
+    pub fn with_raw<R>(mut self, raw: PgDbAgentRawActionParams<R>) -> Self
+    where
+        R: Fn(&PgRow) + Send + Sync + 'static,
+    {
+        self.raws.push(raw.boxed());
+        self
+    }
+
+    /// Register a query whose rows go to an async `RowHandler` on every tick, after the raw queries and before the sinks.
+    pub fn with_handler(mut self, handler: PgDbAgentHandlerParams<T>) -> Self {
+        self.handlers.push(handler);
+        self
+    }
+
+    /// Register a query whose rows are forwarded to a `RowSink` on every tick, after the handlers.
+    pub fn with_sink(mut self, sink: PgDbAgentSinkParams<T>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Stop the agent once this many ticks in a row have failed, any successful tick resets the count.
+    /// Without it a permanently broken query keeps reporting errors forever. A tick failing with an error that
+    /// `with_is_retryable` doesn't accept stops it right away, retrying e.g. a syntax error won't fix it.
+    pub fn with_max_consecutive_errors(mut self, max_consecutive_errors: u32) -> Self {
+        self.max_consecutive_errors = Some(max_consecutive_errors);
+        self
+    }
+
+    /// Stop the agent once this many rows have been handed to query actions, counted across all queries and ticks.
+    /// The row that reaches the limit is still processed, the rest of that tick is skipped.
+    pub fn with_max_total_rows(mut self, max_total_rows: u64) -> Self {
+        self.max_total_rows = Some(max_total_rows);
+        self
+    }
+
+    /// Stop the agent once this many ticks ran, failed ones included, e.g. for bounded jobs or tests driven by
+    /// `trigger_now` that shouldn't depend on timing.
+    pub fn with_max_ticks(mut self, max_ticks: u64) -> Self {
+        self.max_ticks = Some(max_ticks);
+        self
+    }
+
+    /// Ask `should_tick` before every tick, whether scheduled, triggered or notified, and skip the tick when it returns
+    /// `false`, e.g. behind a feature flag or while a downstream queue is too deep. The schedule goes on, the next tick
+    /// asks again. Skipped ticks run nothing and don't count as ticks. See `with_should_tick_async` to await the answer.
+    pub fn with_should_tick<P>(mut self, should_tick: P) -> Self
+    where
+        P: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.should_tick = Some(Box::new(move || future::ready(should_tick()).boxed()));
+        self
+    }
+
+    /// Like `with_should_tick`, for a predicate that needs to await, e.g. a query on another database.
+    pub fn with_should_tick_async<P, Fut>(mut self, should_tick: P) -> Self
+    where
+        P: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.should_tick = Some(Box::new(move || should_tick().boxed()));
+        self
+    }
+
+    /// Cap the rows fetched but not actioned yet, across all queries. A run waits for room before its rows go to the
+    /// actions, so when actions of runs in their own task (an `OverlapPolicy`) fall behind, the next fetch waits for
+    /// them instead of piling rows up in memory. A result larger than the cap waits for the whole buffer.
+    /// `AgentHandle::buffered_rows` reports how full it is. Rows held back by `StandbyPolicy::Buffer` don't count.
+    pub fn with_max_buffered_rows(mut self, max_buffered_rows: usize) -> Self {
+        self.max_buffered_rows = Some(max_buffered_rows);
+        self
+    }
+
+    /// Hold a permit of `limiter` while each of the agent's queries (or query batches) runs, so the agents sharing it
+    /// never have more than its `max` queries running at once, however many of them there are. A query waits for a
+    /// permit like it waits for a connection, actions don't hold one. Share it by cloning it into every agent's params,
+    /// or with `GlobalConcurrencyLimiter::process` where the agents are built independently.
+    pub fn with_global_concurrency_limiter(mut self, limiter: GlobalConcurrencyLimiter) -> Self {
+        self.global_concurrency_limiter = Some(limiter);
+        self
+    }
+
+    /// Skip queries of a lower priority for the rest of the tick when a query returned a full `auto_limit` page,
+    /// i.e. probably has more rows waiting, so a backlog of high priority rows is drained first. Queries without
+    /// `auto_limit` never hold others back. Skipped queries stay due and run on the next tick.
+    pub fn with_strict_priority(mut self, strict_priority: bool) -> Self {
+        self.strict_priority = strict_priority;
+        self
+    }
+
+    /// Stop starting queries once a tick ran for `time_budget`, so a few slow queries can't starve the others when
+    /// many share a tight interval. Queries that didn't get a turn stay due and the next tick gives the first of them
+    /// the first turn among the queries of its priority, round robin; one with a `cursor_bind` resumes from its cursor.
+    /// Higher priorities still go first and `with_run_after` dependencies still run before their dependents. A query
+    /// that started always finishes, fetch and actions, and so do the queries that run after it, so a tick can run
+    /// over by up to one query and its dependents.
+    pub fn with_time_budget_per_tick(mut self, time_budget: Duration) -> Self {
+        self.time_budget_per_tick = Some(time_budget);
+        self
+    }
+
+    /// Send the due queries that share a pool to it in one multi-statement round trip per tick instead of one each,
+    /// for many small queries polled often. Queries with a `cursor_bind`, `before_query`/`after_query` statements, an
+    /// `application_name` or an `OverlapPolicy` still run on their own, as does everything with `with_pinned_connection` or `tick_on`.
+    /// The batch uses the simple query protocol, so values come back as text and `T` must decode from that,
+    /// which the built-in types do. When the batch fails, e.g. because one of its queries does, every query is
+    /// retried on its own in the same tick so the error is reported for the query that caused it.
+    /// Queries held back by `with_strict_priority` are fetched anyway and their rows dropped.
+    pub fn with_batch_queries(mut self, batch_queries: bool) -> Self {
+        self.batch_queries = batch_queries;
+        self
+    }
+
+    /// Run behind PgBouncer (or another pooler) in transaction mode, where a statement prepared on one server
+    /// connection doesn't exist on the next: statements without bind values go out with the simple query protocol,
+    /// so values come back as text and `T` must decode from that, which the built-in types do. Statements with bind
+    /// values (`with_cursor_bind`, outbox deletes and dead letters) are still prepared, unnamed statements need a
+    /// newer sqlx, so those need PgBouncer 1.21+ with `max_prepared_statements` set. The agent sets nothing at
+    /// session level, and `PgDbIdleAgent::new` warns about the session-bound `with_pinned_connection` and `with_notify`.
+    pub fn with_pgbouncer_compatible(mut self, pgbouncer_compatible: bool) -> Self {
+        self.pgbouncer_compatible = pgbouncer_compatible;
+        self
+    }
+
+    /// Fold every actioned row into `initial` with `fold` over the agent's whole lifetime, e.g. to count processed rows
+    /// by category. Read the current value with `AgentHandle::accumulator::<A>()`.
+    pub fn with_accumulator<A, G>(mut self, initial: A, fold: G) -> Self
+    where
+        A: Send + 'static,
+        G: Fn(&mut A, &T) + Send + Sync + 'static,
+    {
+        self.accumulator = Some(Accumulator::new(initial, fold));
+        self
+    }
+
+    /// When a query can't get a connection, e.g. because the pool is saturated (`PoolTimedOut`), retry the acquisition
+    /// with backoff before failing the tick, as long as `with_is_retryable` accepts the error. Only covers acquiring
+    /// the connection, a failing query is not retried.
+    pub fn with_acquire_retry(mut self, acquire_retry: RetryPolicy) -> Self {
+        self.acquire_retry = Some(acquire_retry);
+        self
+    }
+
+    /// Poll less often after failed ticks instead of at the usual pace, back to it after the first tick without errors.
+    /// Only slows down the fixed interval, cron schedules and custom schedulers keep their fire times.
+    pub fn with_error_backoff(mut self, error_backoff: ErrorBackoff) -> Self {
+        self.error_backoff = Some(error_backoff);
+        self
+    }
+
+    /// Decide which errors are worth retrying, for `with_acquire_retry` and `with_max_consecutive_errors`.
+    /// Defaults to `default_is_retryable`, which accepts connection, I/O and pool errors but not query or decode errors.
+    pub fn with_is_retryable<R>(mut self, is_retryable: R) -> Self
+    where
+        R: Fn(&sqlx::Error) -> bool + Send + Sync + 'static,
+    {
+        self.is_retryable = Box::new(is_retryable);
+        self
+    }
+
+    /// When a tick fails with an authentication error (e.g. rotated credentials), build a new pool from the provider's
+    /// current connect options and use it for every query from then on, as `AgentHandle::reconnect` would.
+    pub fn with_credential_provider(mut self, credential_provider: Arc<dyn CredentialProvider>) -> Self {
+        self.credential_provider = Some(credential_provider);
+        self
+    }
+
+    /// Record an `AuditEvent` for every row a query action or handler ran for, with the outcome: a handler's error, or
+    /// a panicking action, which is recorded before the panic goes on. Rows are identified with the query's
+    /// `with_audit_id` (or the handler's). Outboxes, broadcasts, shards, tenant queries, raw queries and sinks aren't
+    /// audited.
+    pub fn with_audit_sink(mut self, audit_sink: Arc<dyn AuditSink>) -> Self {
+        self.audit_sink = Some(audit_sink);
+        self
+    }
+
+    /// Keep the last `recent_errors` errors reported to the error handler, with when they happened and the query that
+    /// failed, for `AgentHandle::recent_errors`. Defaults to 0, which keeps none and costs nothing.
+    pub fn with_recent_errors(mut self, recent_errors: usize) -> Self {
+        self.recent_errors = recent_errors;
+        self
+    }
+
+    /// Called with every row right after a query's action ran for it, also when the action panicked (the panic goes on
+    /// afterwards), e.g. to zeroize a decrypted secret the row holds. Rows a run never hands to the action, because it
+    /// was cancelled or `max_total_rows` was reached, are only dropped. Outboxes, broadcasts, shards, tenant queries,
+    /// raw queries, handlers and sinks don't call it.
+    pub fn with_on_row_finalize<H>(mut self, on_row_finalize: H) -> Self
+    where
+        H: Fn(&T) + Send + Sync + 'static,
+    {
+        self.on_row_finalize = Some(Arc::new(on_row_finalize));
+        self
+    }
+
+    /// What to do once a pool the queries run on was closed, stops the agent by default.
+    pub fn with_pool_closed_policy(mut self, pool_closed_policy: PoolClosedPolicy) -> Self {
+        self.pool_closed_policy = pool_closed_policy;
+        self
+    }
+
+    /// Acquire a single connection from `pool` and run every query on it for the agent's lifetime instead of on the
+    /// queries' own pools, for session-scoped state like temp tables, session advisory locks or `SET SESSION`.
+    /// When the connection is lost the tick fails and the next one acquires a new connection, without the session
+    /// state of the old one. Actions still write through the pools, and `AgentHandle::reconnect` doesn't apply.
+    pub fn with_pinned_connection(mut self, pool: PgPool) -> Self {
+        self.pinned_pool = Some(pool);
+        self
+    }
+
+    /// Called after every tick in which a query measured with `with_size_hint` ran, with the bytes those queries fetched.
+    pub fn with_on_tick_bytes<H>(mut self, on_tick_bytes: H) -> Self
+    where
+        H: Fn(u64) + Send + Sync + 'static,
+    {
+        self.on_tick_bytes = Some(Box::new(on_tick_bytes));
+        self
+    }
+
+    /// Time every call of the query actions and log the percentiles per query at the end of each tick, to tell
+    /// whether the queries or the actions are the bottleneck without a metrics backend. Costs two clock reads per row.
+    pub fn with_time_actions(mut self, time_actions: bool) -> Self {
+        self.time_actions = time_actions;
+        self
+    }
+
+    /// Like `with_time_actions`, but the timings are handed to `on_action_timings` instead of being logged.
+    pub fn with_on_action_timings<H>(mut self, on_action_timings: H) -> Self
+    where
+        H: Fn(&ActionTimings) + Send + Sync + 'static,
+    {
+        self.time_actions = true;
+        self.on_action_timings = Some(Box::new(on_action_timings));
+        self
+    }
+
+    /// Start the agent on standby: queries keep running and cursors keep advancing, but rows are buffered or dropped
+    /// per `policy` instead of being acted on, and outboxes, broadcasts, shards, tenant queries, raw queries, handlers
+    /// and sinks don't run, until `AgentHandle::activate` is called.
+    pub fn with_standby(mut self, policy: StandbyPolicy) -> Self {
+        self.standby = Some(policy);
+        self
+    }
+
+    /// Called once when the agent's loop stops, right before its task finishes.
+    /// Not called when the task is aborted.
+    pub fn with_on_stop<S>(mut self, on_stop: S) -> Self
+    where
+        S: Fn(StopReason) + Send + Sync + 'static,
+    {
+        self.on_stop = Some(Box::new(on_stop));
+        self
+    }
+
+    /// Called once with the agent's lifetime totals when its loop stops, right after `on_stop`.
+    /// Not called when the task is aborted.
+    pub fn with_on_complete<C>(mut self, on_complete: C) -> Self
+    where
+        C: Fn(AgentSummary) + Send + Sync + 'static,
+    {
+        self.on_complete = Some(Box::new(on_complete));
+        self
+    }
+
+    /// Run `query` once the agent's loop stopped, after `on_stop` and `on_complete` and right before the task
+    /// resolves, e.g. to `VACUUM` the table a drain job emptied or flip a status flag. `on_result` gets the rows it
+    /// affected or its error. It goes out with the simple query protocol, so it can hold several statements and
+    /// run ones that refuse a transaction block. Not run when the task is aborted or the schedule was invalid.
+    pub fn with_on_complete_query<R>(mut self, pool: PgPool, query: impl Into<String>, on_result: R) -> Self
+    where
+        R: Fn(Result<u64, sqlx::Error>) + Send + Sync + 'static,
+    {
+        self.on_complete_query = Some(CompleteQuery {
+            pool,
+            query: query.into(),
+            on_result: Box::new(on_result),
+        });
+        self
+    }
+
+    /// Called when the moving average of the last `lag_ticks` tick durations has exceeded the tick interval
+    /// for `lag_ticks` consecutive ticks, so a single slow tick doesn't trigger it but a systemic backlog does.
+    /// Fires once per lagging streak, the streak ends as soon as the average drops back to the interval.
+    pub fn with_on_sustained_lag<L>(mut self, lag_ticks: u32, on_sustained_lag: L) -> Self
+    where
+        L: Fn(LagReport) + Send + Sync + 'static,
+    {
+        self.lag_ticks = lag_ticks;
+        self.on_sustained_lag = Some(Box::new(on_sustained_lag));
+        self
+    }
+
+    /// Called whenever a run moves the cursor of a query with a `cursor_bind`, with the query's name (its text if it
+    /// has none) and the new cursor, e.g. to persist it so a restarted agent resumes from it with
+    /// `PgDbIdleAgent::with_cursor` instead of processing the rows again.
+    pub fn with_on_cursor_advance<C>(mut self, on_cursor_advance: C) -> Self
+    where
+        C: Fn(&str, BindValue) + Send + Sync + 'static,
+    {
+        self.on_cursor_advance = Some(Box::new(on_cursor_advance));
+        self
+    }
+
+    /// Called with the drift of every scheduled tick that fired more than `threshold` after the schedule planned it,
+    /// see `AgentHandle::drift`.
+    pub fn with_on_drift_exceeded<D>(mut self, threshold: Duration, on_drift_exceeded: D) -> Self
+    where
+        D: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_drift_exceeded = Some((threshold, Box::new(on_drift_exceeded)));
+        self
+    }
+
+    /// Replace the fixed interval with another schedule, e.g. `Schedule::Cron` with the `cron` feature.
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Tick whenever `scheduler` says so instead of following `with_schedule`. Like with cron schedules every query
+    /// runs on every tick, per-query intervals are ignored, and `AgentHandle::trigger_now` runs an extra tick
+    /// without resetting the scheduler.
+    pub fn with_scheduler<S>(mut self, scheduler: S) -> Self
+    where
+        S: Scheduler + 'static,
+    {
+        self.scheduler = Some(Box::new(scheduler));
+        self
+    }
+
+    /// What the interval does with ticks missed while a tick ran long or the runtime was stalled,
+    /// `MissedTickBehavior::Burst` (fire them all right away) by default. Only applies to the built-in interval.
+    pub fn with_missed_tick_behavior(mut self, missed_tick_behavior: MissedTickBehavior) -> Self {
+        self.missed_tick_behavior = missed_tick_behavior;
+        self
+    }
+
+    /// Fire at most `max_catchup_ticks` immediate ticks after a late one, then return to the normal cadence
+    /// a full interval later. Only matters with `MissedTickBehavior::Burst`, the other behaviors never burst.
+    pub fn with_max_catchup_ticks(mut self, max_catchup_ticks: usize) -> Self {
+        self.max_catchup_ticks = Some(max_catchup_ticks);
+        self
+    }
+
+    /// Fail ticks at random with a synthetic `sqlx::Error::Io` before they run any query, for resilience tests.
+    /// Failures go through the error handler, `with_max_consecutive_errors` (an I/O error is one `with_is_retryable`
+    /// accepts by default) and `with_error_backoff` like real ones.
+    #[cfg(feature = "fault-injection")]
+    pub fn with_fault_injection(mut self, fault_injection: crate::FaultConfig) -> Self {
+        self.fault_injection = Some(fault_injection);
+        self
+    }
+
+    /// Timezone cron expressions are evaluated in, UTC by default.
+    #[cfg(feature = "cron")]
+    pub fn with_cron_timezone(mut self, cron_timezone: chrono_tz::Tz) -> Self {
+        self.cron_timezone = cron_timezone;
+        self
+    }
+
+    /// Wait for the rate limiter before every action invocation, across all queries.
+    /// The limiter can be shared with other agents or code paths to cap the combined rate against a downstream.
+    #[cfg(feature = "governor")]
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<governor::DefaultDirectRateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Write every tick start, fetch, finished action and reported error to `debug_sink` as a JSON line (`AgentEvent`).
+    /// Meant for local debugging, writes are synchronous and their errors are ignored.
+    #[cfg(feature = "serde")]
+    pub fn with_debug_sink(mut self, debug_sink: crate::DebugSink) -> Self {
+        self.debug_sink = Some(debug_sink);
+        self
+    }
+
+    /// Acquires a connection from every pool the agent would use once, so a bad connection string or a closed pool
+    /// fails here instead of being reported on every tick. Pools that aren't a query's are reported as
+    /// `LISTEN <channels>` for `with_notify` and `pinned connection` for `with_pinned_connection`.
+    pub async fn validate_pools(self) -> Result<Self, ParamsError> {
+        let mut pools: Vec<(Cow<'_, str>, &PgPool)> = Vec::new();
+        for query_action in &self.query_actions {
+            pools.push((query_action.query.as_str().into(), &query_action.pool));
+            if let Some(write_pool) = &query_action.write_pool {
+                pools.push((query_action.query.as_str().into(), write_pool));
+            }
+        }
+        pools.extend(self.outboxes.iter().map(|outbox| (outbox.query.as_str().into(), &outbox.pool)));
+        pools.extend(self.broadcasts.iter().map(|broadcast| (broadcast.query.as_str().into(), &broadcast.pool)));
+        pools.extend(self.handlers.iter().map(|handler| (handler.query.as_str().into(), &handler.pool)));
+        pools.extend(self.sinks.iter().map(|sink| (sink.query.as_str().into(), &sink.pool)));
+        pools.extend(self.tenants.iter().map(|tenant| (tenant.query.as_str().into(), &tenant.pool)));
+        pools.extend(self.raws.iter().map(|raw| (raw.query.as_str().into(), &raw.pool)));
+        for sharded in &self.shards {
+            pools.extend(sharded.pools.iter().map(|pool| (sharded.query.as_str().into(), pool)));
+        }
+        if let Some(notify) = &self.notify {
+            let channels: Vec<&str> = notify
+                .channels
+                .iter()
+                .chain(notify.channel_queries.keys())
+                .map(String::as_str)
+                .collect();
+            pools.push((format!("LISTEN {}", channels.join(", ")).into(), &notify.pool));
+        }
+        if let Some(pinned_pool) = &self.pinned_pool {
+            pools.push(("pinned connection".into(), pinned_pool));
+        }
+        if let Some(complete_query) = &self.on_complete_query {
+            pools.push((complete_query.query.as_str().into(), &complete_query.pool));
+        }
+        for (query, pool) in pools {
+            if let Err(e) = pool.acquire().await {
+                return Err(ParamsError::UnusablePool {
+                    query: query.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+        Ok(self)
+    }
+
+    /// Prepares every query that declared `with_expected_columns` and checks that it returns those columns,
+    /// failing with `ParamsError::ColumnMismatch` on the first one that doesn't. Nothing is executed.
+    pub async fn validate_columns(self) -> Result<Self, ParamsError> {
+        for query_action in self.query_actions.iter().filter(|query_action| !query_action.expected_columns.is_empty()) {
+            let statement = query_action.statement();
+            if let Some(reason) = expected_column::mismatch(&query_action.pool, &statement, &query_action.expected_columns).await {
+                return Err(ParamsError::ColumnMismatch {
+                    query: query_action.query.clone(),
+                    reason,
+                });
+            }
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn effective_interval(&self, query_action: &PgDbAgentQueryActionParams<T, F>) -> Duration {
+        query_action.interval.unwrap_or(self.interval_secs)
+    }
+
+    /// The agent ticks at the shortest effective interval and runs each query once it is due,
+    /// so longer per-query intervals are rounded up to a multiple of this one.
+    pub(crate) fn tick_interval(&self) -> Duration {
+        self.query_actions
+            .iter()
+            .map(|query_action| self.effective_interval(query_action))
+            .min()
+            .unwrap_or(self.interval_secs)
+    }
+}
+
+
+
+/// Debounce windows a burst of notifications is held back at most, unless `with_debounce_max_wait` sets another wait.
+pub const DEBOUNCE_MAX_WAIT_WINDOWS: u32 = 10;
+
+/// LISTEN/NOTIFY trigger configuration.
+/// Notifications arriving within `notify_debounce` of each other are coalesced into a single tick,
+/// which fires once the channels have been quiet for the whole window, or at the latest after `debounce_max_wait`
+/// so a steady stream of notifications still ticks.
+pub struct PgDbAgentNotifyParams {
+    pub pool: PgPool,
+    pub channels: Vec<String>,
+    pub notify_debounce: Duration,
+    /// Longest a tick waits for the channels to go quiet, `DEBOUNCE_MAX_WAIT_WINDOWS` windows unless set.
+    pub debounce_max_wait: Option<Duration>,
+    /// Names of the queries each mapped channel runs, see `with_channel_query`.
+    pub channel_queries: HashMap<String, Vec<String>>,
+}
+
+impl PgDbAgentNotifyParams {
+    pub fn new(pool: PgPool, channels: Vec<String>, notify_debounce: Duration) -> Self {
+        Self {
+            pool,
+            channels,
+            notify_debounce,
+            debounce_max_wait: None,
+            channel_queries: HashMap::new(),
+        }
+    }
+
+    /// Tick at the latest `debounce_max_wait` after the first notification of a burst, even if notifications keep
+    /// arriving within `notify_debounce` of each other.
+    pub fn with_debounce_max_wait(mut self, debounce_max_wait: Duration) -> Self {
+        self.debounce_max_wait = Some(debounce_max_wait);
+        self
+    }
+
+    pub(crate) fn effective_debounce_max_wait(&self) -> Duration {
+        self.debounce_max_wait
+            .unwrap_or(self.notify_debounce * DEBOUNCE_MAX_WAIT_WINDOWS)
+    }
+
+    /// Also listen on `channel` and have its notifications run only the query named `query_name` (see
+    /// `PgDbAgentQueryActionParams::with_name`), call it again to map more queries to the same channel.
+    /// A tick run by mapped channels only runs their queries, outboxes, broadcasts, shards, tenant queries, raw
+    /// queries, handlers and sinks wait for a tick that runs every query. Notifications on unmapped channels keep
+    /// running everything. `PgDbAgentParams::with_notify` fails if no query is named `query_name`.
+    pub fn with_channel_query(mut self, channel: impl Into<String>, query_name: impl Into<String>) -> Self {
+        self.channel_queries
+            .entry(channel.into())
+            .or_default()
+            .push(query_name.into());
+        self
+    }
+}
+
+
+
+/// Read-only view of a registered query, see `PgDbIdleAgent::queries`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PgDbAgentQueryInfo<'a> {
+    pub query: &'a str,
+    pub name: Option<&'a str>,
+    pub interval: Duration,
+}
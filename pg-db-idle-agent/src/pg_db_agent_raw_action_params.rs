@@ -0,0 +1,40 @@
+use sqlx::{postgres::PgRow, PgPool};
+
+pub type RawAction = Box<dyn Fn(&PgRow) + Send + Sync>;
+
+/// Hands every row `query` returns to `action` as the `PgRow` itself, without decoding it into a `T`, for rows whose
+/// shape is only known at runtime, e.g. a table inspector polling tables it discovered. Columns are read by name or
+/// index with `sqlx::Row::try_get`.
+///
+/// An agent that only runs raw queries can use `()` as its `T`, with no query actions.
+pub struct PgDbAgentRawActionParams<F = RawAction>
+where
+    F: Fn(&PgRow) + Send + Sync + 'static,
+{
+    pub pool: PgPool,
+    pub query: String,
+    pub action: F,
+}
+
+impl<F> PgDbAgentRawActionParams<F>
+where
+    F: Fn(&PgRow) + Send + Sync + 'static,
+{
+    pub fn new(pool: PgPool, query: String, action: F) -> Self {
+        Self { pool, query, action }
+    }
+
+    pub(crate) fn boxed(self) -> PgDbAgentRawActionParams {
+        PgDbAgentRawActionParams {
+            pool: self.pool,
+            query: self.query,
+            action: Box::new(self.action),
+        }
+    }
+
+    pub(crate) async fn process(&self, pool: &PgPool, persistent: bool) -> Result<(), sqlx::Error> {
+        let rows = crate::unprepared::fetch_rows(pool, &self.query, persistent).await?;
+        rows.iter().for_each(|row| (self.action)(row));
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::marker::PhantomData;
+
+use futures::future;
+use sqlx::{postgres::PgRow, PgPool};
+
+pub type ShardedAction<T> = Box<dyn Fn(&T, usize) + Send + Sync>;
+
+/// Runs the same `query` against every shard in `pools` concurrently and hands each row to `action`
+/// together with the index of the shard (in `pools`) it came from.
+///
+/// A failing shard is reported to the agent's error handler and doesn't fail the tick, the rows of the other
+/// shards are still processed. `AgentHandle::reconnect` doesn't apply, shards always use their own pools.
+pub struct PgDbAgentShardedActionParams<T, F = ShardedAction<T>>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: Fn(&T, usize) + Send + Sync + 'static,
+{
+    pub pools: Vec<PgPool>,
+    pub query: String,
+    pub action: F,
+    pub _marker: PhantomData<T>,
+}
+
+impl<T, F> PgDbAgentShardedActionParams<T, F>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: Fn(&T, usize) + Send + Sync + 'static,
+{
+    pub fn new(pools: Vec<PgPool>, query: String, action: F) -> Self {
+        Self {
+            pools,
+            query,
+            action,
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn boxed(self) -> PgDbAgentShardedActionParams<T> {
+        PgDbAgentShardedActionParams {
+            pools: self.pools,
+            query: self.query,
+            action: Box::new(self.action),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Rows are delivered shard by shard in `pools` order once every shard answered.
+    pub(crate) async fn process<H>(&self, persistent: bool, on_shard_error: H)
+    where
+        H: Fn(sqlx::Error),
+    {
+        let results = future::join_all(
+            self.pools
+                .iter()
+                .map(|pool| crate::unprepared::fetch_all(pool, &self.query, persistent)),
+        )
+        .await;
+        for (shard, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(rows) => rows.iter().for_each(|row| (self.action)(row, shard)),
+                Err(e) => on_shard_error(e),
+            }
+        }
+    }
+}
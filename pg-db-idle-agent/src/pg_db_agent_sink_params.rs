@@ -0,0 +1,63 @@
+use sqlx::{postgres::PgRow, PgPool};
+
+use crate::{RowSink, SinkError};
+
+pub type SinkErrorHandler = Box<dyn Fn(SinkError) + Send + Sync>;
+
+/// Runs `query` on every tick, sends each row to `sink` in order and flushes it once the rows were sent.
+/// A failing `send` skips the rest of the tick's rows, the error is reported to `on_sink_error` either way.
+pub struct PgDbAgentSinkParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub pool: PgPool,
+    pub query: String,
+    pub sink: Box<dyn RowSink<T>>,
+    pub on_sink_error: Option<SinkErrorHandler>,
+}
+
+impl<T> PgDbAgentSinkParams<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+{
+    pub fn new<S>(pool: PgPool, query: String, sink: S) -> Self
+    where
+        S: RowSink<T>,
+    {
+        Self {
+            pool,
+            query,
+            sink: Box::new(sink),
+            on_sink_error: None,
+        }
+    }
+
+    /// Called with every error returned by the sink's `send` or `flush`.
+    pub fn with_sink_error_handler<E>(mut self, on_sink_error: E) -> Self
+    where
+        E: Fn(SinkError) + Send + Sync + 'static,
+    {
+        self.on_sink_error = Some(Box::new(on_sink_error));
+        self
+    }
+
+    pub(crate) async fn process(&self, pool: &PgPool, persistent: bool) -> Result<(), sqlx::Error> {
+        let rows: Vec<T> = crate::unprepared::fetch_all(pool, &self.query, persistent).await?;
+        for row in rows {
+            if let Err(e) = self.sink.send(row).await {
+                self.report(e);
+                break;
+            }
+        }
+        if let Err(e) = self.sink.flush().await {
+            self.report(e);
+        }
+        Ok(())
+    }
+
+    fn report(&self, e: SinkError) {
+        if let Some(on_sink_error) = &self.on_sink_error {
+            on_sink_error(e);
+        }
+    }
+}
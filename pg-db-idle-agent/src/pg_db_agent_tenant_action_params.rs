@@ -0,0 +1,97 @@
+use std::{
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use sqlx::{postgres::PgRow, PgPool};
+
+use crate::BindValue;
+
+pub type TenantAction<T> = Box<dyn Fn(&T, &BindValue) + Send + Sync>;
+
+/// Runs the same `query` once per tenant on every tick and hands each row to `action` together with the id of the
+/// tenant it was fetched for, instead of a query action per tenant.
+///
+/// The query gets the tenant id in `$tenant` (or `$1`), e.g. `SELECT * FROM jobs WHERE tenant_id = $tenant`. Ids that
+/// aren't a `BindValue` type are passed as `Text` and cast in the query, e.g. `$tenant::uuid`. A failing tenant is
+/// reported to the agent's error handler and doesn't fail the tick, the other tenants are still processed.
+pub struct PgDbAgentTenantActionParams<T, F = TenantAction<T>>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: Fn(&T, &BindValue) + Send + Sync + 'static,
+{
+    pub pool: PgPool,
+    pub query: String,
+    pub tenants: Vec<BindValue>,
+    pub action: F,
+    /// Tenants polled per tick, `None` polls all of them.
+    pub tenants_per_tick: Option<usize>,
+    /// Index in `tenants` the next tick starts at, with `tenants_per_tick`.
+    next_tenant: AtomicUsize,
+    pub _marker: PhantomData<T>,
+}
+
+impl<T, F> PgDbAgentTenantActionParams<T, F>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Sync + Unpin + 'static,
+    F: Fn(&T, &BindValue) + Send + Sync + 'static,
+{
+    pub fn new(pool: PgPool, query: String, tenants: Vec<BindValue>, action: F) -> Self {
+        Self {
+            pool,
+            query: query.replace("$tenant", "$1"),
+            tenants,
+            action,
+            tenants_per_tick: None,
+            next_tenant: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Poll only `tenants_per_tick` tenants per tick, round-robin: every tick picks up after the last tenant of the
+    /// previous one, so each tenant is polled every `tenants.len() / tenants_per_tick` ticks and the load of a tick
+    /// stays the same however many tenants there are.
+    pub fn with_tenants_per_tick(mut self, tenants_per_tick: usize) -> Self {
+        self.tenants_per_tick = Some(tenants_per_tick.max(1));
+        self
+    }
+
+    pub(crate) fn boxed(self) -> PgDbAgentTenantActionParams<T> {
+        PgDbAgentTenantActionParams {
+            pool: self.pool,
+            query: self.query,
+            tenants: self.tenants,
+            action: Box::new(self.action),
+            tenants_per_tick: self.tenants_per_tick,
+            next_tenant: self.next_tenant,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Tenants of this tick, in order.
+    fn tenants_of_tick(&self) -> impl Iterator<Item = &BindValue> {
+        let count = self.tenants_per_tick.map_or(self.tenants.len(), |per_tick| per_tick.min(self.tenants.len()));
+        let start = match self.tenants.len() {
+            0 => 0,
+            len => self.next_tenant.fetch_add(count, Ordering::Relaxed) % len,
+        };
+        self.tenants.iter().cycle().skip(start).take(count)
+    }
+
+    /// Tenants are polled one after the other, so a tick holds a single connection of `pool` at a time.
+    pub(crate) async fn process<H>(&self, pool: &PgPool, persistent: bool, on_tenant_error: H)
+    where
+        H: Fn(sqlx::Error),
+    {
+        for tenant in self.tenants_of_tick() {
+            let result = sqlx::query_as_with::<_, T, _>(&self.query, BindValue::arguments(Some(tenant)))
+                .persistent(persistent)
+                .fetch_all(pool)
+                .await;
+            match result {
+                Ok(rows) => rows.iter().for_each(|row| (self.action)(row, tenant)),
+                Err(e) => on_tenant_error(e),
+            }
+        }
+    }
+}
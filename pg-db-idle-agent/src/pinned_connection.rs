@@ -0,0 +1,50 @@
+use sqlx::{pool::PoolConnection, PgPool, Postgres};
+
+use crate::{retry_policy::IsRetryable, RetryPolicy};
+
+/// The one connection every query runs on with `PgDbAgentParams::with_pinned_connection`.
+pub(crate) struct PinnedConnection {
+    pool: PgPool,
+    connection: Option<PoolConnection<Postgres>>,
+}
+
+impl PinnedConnection {
+    pub(crate) fn new(pool: PgPool) -> Self {
+        Self { pool, connection: None }
+    }
+
+    /// The pinned connection, acquired first if there is none yet or the previous one was lost.
+    pub(crate) async fn get(
+        &mut self,
+        acquire_retry: Option<&RetryPolicy>,
+        is_retryable: &IsRetryable,
+    ) -> Result<&mut PoolConnection<Postgres>, sqlx::Error> {
+        if self.connection.is_none() {
+            let connection = match acquire_retry {
+                Some(acquire_retry) => acquire_retry.acquire(&self.pool, is_retryable).await?,
+                None => self.pool.acquire().await?,
+            };
+            self.connection = Some(connection);
+        }
+        Ok(self.connection.as_mut().expect("connection was just acquired"))
+    }
+
+    /// Drops the connection if `e` means it is gone, so the next `get` reconnects. Session state is lost with it.
+    pub(crate) fn check(&mut self, e: &sqlx::Error) {
+        if is_connection_lost(e) {
+            self.connection = None;
+        }
+    }
+}
+
+/// I/O and protocol failures, plus the SQLSTATEs of connection exceptions (class 08) and of a backend being
+/// terminated (57P01 to 57P03).
+pub(crate) fn is_connection_lost(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::Protocol(_) | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(e) => e
+            .code()
+            .is_some_and(|code| code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03")),
+        _ => false,
+    }
+}
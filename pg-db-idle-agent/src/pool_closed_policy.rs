@@ -0,0 +1,11 @@
+/// What the agent does when it finds a pool its queries run on closed at the start of a tick, see
+/// `PgDbAgentParams::with_pool_closed_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoolClosedPolicy {
+    /// Stop the agent with `StopReason::PoolClosed` instead of failing every tick from then on.
+    #[default]
+    Stop,
+    /// Build a new pool from the `CredentialProvider` and use it for every query, as `AgentHandle::reconnect` would.
+    /// Stops like `Stop` when no provider is configured.
+    Reconnect,
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use sqlx::{
+    postgres::{PgConnectOptions, PgRow},
+    Either, PgPool,
+};
+
+/// Queries of one tick that run on the same pool, sent together by `PgDbAgentParams::with_batch_queries`.
+pub(crate) struct QueryBatch<'a> {
+    /// Clones of a pool share their options, which tells pools apart since `PgPool` has no identity of its own.
+    options: Arc<PgConnectOptions>,
+    pool: &'a PgPool,
+    /// Indices into `query_actions` and the statements to run for them.
+    pub(crate) queries: Vec<(usize, String)>,
+}
+
+impl<'a> QueryBatch<'a> {
+    /// Adds a query to the batch of its pool in `batches`, starting a new batch for a pool not seen yet.
+    pub(crate) fn add(batches: &mut Vec<Self>, pool: &'a PgPool, index: usize, statement: String) {
+        let options = pool.connect_options();
+        match batches.iter_mut().find(|batch| Arc::ptr_eq(&batch.options, &options)) {
+            Some(batch) => batch.queries.push((index, statement)),
+            None => batches.push(Self {
+                options,
+                pool,
+                queries: vec![(index, statement)],
+            }),
+        }
+    }
+
+    /// Runs every statement in one simple-query round trip and splits the rows by statement, in `queries` order.
+    /// Postgres runs them in one implicit transaction, so one failing statement fails them all.
+    pub(crate) async fn fetch(&self) -> Result<Vec<Vec<PgRow>>, sqlx::Error> {
+        let sql = self
+            .queries
+            .iter()
+            .map(|(_, statement)| statement.trim().trim_end_matches(';'))
+            .collect::<Vec<_>>()
+            .join(";\n");
+        let mut results = Vec::with_capacity(self.queries.len());
+        let mut rows = Vec::new();
+        let mut stream = sqlx::raw_sql(&sql).fetch_many(self.pool);
+        while let Some(item) = stream.try_next().await? {
+            match item {
+                Either::Left(_) => results.push(std::mem::take(&mut rows)),
+                Either::Right(row) => rows.push(row),
+            }
+        }
+        Ok(results)
+    }
+}
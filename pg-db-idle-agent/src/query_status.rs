@@ -0,0 +1,154 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, PoisonError,
+};
+
+use sqlx::PgPool;
+
+use crate::{
+    order_by::{self, Ordering as QueryOrdering},
+    BindValue, OrderBy, OrderByError,
+};
+
+/// Runtime state of a named query, see `AgentHandle::status_for`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryStatus {
+    pub enabled: bool,
+    /// Times the query ran, failed runs included.
+    pub runs: u64,
+    /// Rows the last successful run returned, `None` until one succeeded.
+    pub last_row_count: Option<usize>,
+    /// Error of the last run, `None` if it succeeded.
+    pub last_error: Option<String>,
+    /// Approximate bytes the last successful run fetched, `None` without `with_size_hint`.
+    pub last_bytes: Option<u64>,
+}
+
+/// Per query state shared with `AgentHandle`, in the same order as `query_actions`.
+pub(crate) struct QueryShared {
+    pub(crate) name: Option<String>,
+    /// Identifies the query in an `AgentState`, its name or, without one, its text.
+    pub(crate) state_key: String,
+    enabled: AtomicBool,
+    /// Set by `AgentHandle::remove_query`, the query never runs again and can't be addressed by name anymore.
+    removed: AtomicBool,
+    status: Mutex<QueryStatus>,
+    /// `cursor_bind` of the last row fetched so far, `None` until a run returned rows.
+    cursor: Mutex<Option<BindValue>>,
+    /// What the SQL sent to the database is built from and its pool, for `explain` and `set_order_by`.
+    query: String,
+    limit: Option<usize>,
+    ordering: Option<Arc<QueryOrdering>>,
+    pool: PgPool,
+    /// `cursor_bind(None)`, bound by `explain` until the query returned rows.
+    initial_cursor: Option<BindValue>,
+}
+
+impl QueryShared {
+    pub(crate) fn new(
+        name: Option<String>,
+        query: &str,
+        limit: Option<usize>,
+        ordering: Option<Arc<QueryOrdering>>,
+        pool: PgPool,
+        initial_cursor: Option<BindValue>,
+    ) -> Self {
+        Self {
+            state_key: name.clone().unwrap_or_else(|| query.to_string()),
+            name,
+            enabled: AtomicBool::new(true),
+            removed: AtomicBool::new(false),
+            status: Mutex::default(),
+            cursor: Mutex::default(),
+            query: query.to_string(),
+            limit,
+            ordering,
+            pool,
+            initial_cursor,
+        }
+    }
+
+    /// Pool the query was configured with.
+    pub(crate) fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Plan of the query with its current cursor bound, one line per row of `EXPLAIN`'s output. Runs in a transaction
+    /// that is rolled back, so `analyze` doesn't keep the effects of the query (e.g. its row locks).
+    pub(crate) async fn explain(&self, pool: &PgPool, analyze: bool) -> Result<String, sqlx::Error> {
+        let options = if analyze { "(ANALYZE) " } else { "" };
+        let statement = format!(
+            "EXPLAIN {}{}",
+            options,
+            order_by::statement(&self.query, self.ordering.as_deref(), self.limit)
+        );
+        let cursor = self.cursor().or_else(|| self.initial_cursor.clone());
+        let mut tx = pool.begin().await?;
+        let plan: Vec<String> = sqlx::query_scalar_with(&statement, BindValue::arguments(cursor.as_ref()))
+            .fetch_all(&mut *tx)
+            .await?;
+        tx.rollback().await?;
+        Ok(plan.join("\n"))
+    }
+
+    pub(crate) fn order_by(&self) -> Option<OrderBy> {
+        self.ordering.as_ref().map(|ordering| ordering.current())
+    }
+
+    pub(crate) fn set_order_by(&self, order_by: OrderBy) -> Result<(), OrderByError> {
+        let ordering = self
+            .ordering
+            .as_ref()
+            .ok_or_else(|| OrderByError::Unordered(self.state_key.clone()))?;
+        ordering.set(&self.state_key, order_by, self.initial_cursor.is_some())
+    }
+
+    pub(crate) fn cursor(&self) -> Option<BindValue> {
+        self.cursor.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub(crate) fn set_cursor(&self, cursor: BindValue) {
+        *self.cursor.lock().unwrap_or_else(PoisonError::into_inner) = Some(cursor);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::SeqCst)
+    }
+
+    /// Disables the query for good, `false` if it was removed already.
+    pub(crate) fn remove(&self) -> bool {
+        self.set_enabled(false);
+        !self.removed.swap(true, Ordering::SeqCst)
+    }
+
+    pub(crate) fn record_run<R>(&self, result: &Result<Vec<R>, sqlx::Error>) {
+        let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+        status.runs += 1;
+        match result {
+            Ok(rows) => {
+                status.last_row_count = Some(rows.len());
+                status.last_error = None;
+            }
+            Err(e) => status.last_error = Some(e.to_string()),
+        }
+    }
+
+    pub(crate) fn record_bytes(&self, bytes: u64) {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner).last_bytes = Some(bytes);
+    }
+
+    pub(crate) fn status(&self) -> QueryStatus {
+        QueryStatus {
+            enabled: self.is_enabled(),
+            ..self.status.lock().unwrap_or_else(PoisonError::into_inner).clone()
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// An error the agent reported, see `AgentHandle::recent_errors`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ErrorRecord {
+    pub at: SystemTime,
+    /// Name, or text, of the query whose run failed, `None` for errors of anything else, e.g. an outbox or LISTEN.
+    pub query: Option<String>,
+    pub message: String,
+}
+
+/// The last errors reported, oldest first, see `PgDbAgentParams::with_recent_errors`.
+#[derive(Default)]
+pub(crate) struct RecentErrors {
+    capacity: usize,
+    errors: Mutex<VecDeque<ErrorRecord>>,
+}
+
+impl RecentErrors {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            errors: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub(crate) fn record(&self, query: Option<String>, e: &sqlx::Error) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut errors = self.errors.lock().unwrap_or_else(PoisonError::into_inner);
+        if errors.len() == self.capacity {
+            errors.pop_front();
+        }
+        errors.push_back(ErrorRecord {
+            at: SystemTime::now(),
+            query,
+            message: e.to_string(),
+        });
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<ErrorRecord> {
+        self.errors.lock().unwrap_or_else(PoisonError::into_inner).iter().cloned().collect()
+    }
+}
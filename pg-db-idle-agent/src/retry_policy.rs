@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use sqlx::{pool::PoolConnection, PgPool, Postgres};
+
+use crate::pinned_connection::is_connection_lost;
+
+/// Decides whether an error may go away by trying again, see `PgDbAgentParams::with_is_retryable`.
+pub type IsRetryable = Box<dyn Fn(&sqlx::Error) -> bool + Send + Sync>;
+
+/// How often and how patiently to retry, the backoff doubles after every attempt up to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Acquires a connection, retrying the errors `is_retryable` accepts. Any other error, or the last retryable one,
+    /// is returned.
+    pub(crate) async fn acquire(
+        &self,
+        pool: &PgPool,
+        is_retryable: &IsRetryable,
+    ) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+        let mut backoff = self.initial_backoff;
+        let mut retries = 0;
+        loop {
+            match pool.acquire().await {
+                Err(e) if retries < self.max_retries && is_retryable(&e) => {
+                    log::warn!("Failed to acquire a connection ({}), retrying in {:?}", e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2).min(self.max_backoff);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The default classification: a lost or refused connection, a saturated pool or server (`PoolTimedOut`, SQLSTATE
+/// 53300) and a transaction rolled back by a serialization failure or deadlock (40001, 40P01) are worth retrying.
+/// Syntax, permission, constraint and decode errors fail the same way every time.
+pub fn default_is_retryable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(db) => {
+            is_connection_lost(e) || db.code().is_some_and(|code| matches!(code.as_ref(), "40001" | "40P01" | "53300"))
+        }
+        _ => is_connection_lost(e),
+    }
+}
@@ -0,0 +1,61 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{RowAction, RowContext};
+
+type Route<T> = Box<dyn Fn(&T) + Send + Sync>;
+
+/// Action that dispatches each row to the action registered for its key, like a `match` on `key(row)`.
+/// Rows whose key has no route go to the default action, or are skipped with a warning when there is none.
+pub struct Router<T, K> {
+    key: Box<dyn Fn(&T) -> K + Send + Sync>,
+    routes: HashMap<K, Route<T>>,
+    default: Option<Route<T>>,
+}
+
+impl<T, K> Router<T, K>
+where
+    K: Eq + Hash,
+{
+    pub fn new<R>(key: R) -> Self
+    where
+        R: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        Self {
+            key: Box::new(key),
+            routes: HashMap::new(),
+            default: None,
+        }
+    }
+
+    /// Send rows whose key is `key` to `action`, replacing an action registered for the same key earlier.
+    pub fn route<A>(mut self, key: K, action: A) -> Self
+    where
+        A: Fn(&T) + Send + Sync + 'static,
+    {
+        self.routes.insert(key, Box::new(action));
+        self
+    }
+
+    /// Action for rows whose key has no route, e.g. a dead-letter handler.
+    pub fn with_default<A>(mut self, action: A) -> Self
+    where
+        A: Fn(&T) + Send + Sync + 'static,
+    {
+        self.default = Some(Box::new(action));
+        self
+    }
+}
+
+impl<T, K> RowAction<T> for Router<T, K>
+where
+    T: 'static,
+    K: Eq + Hash + std::fmt::Debug + 'static,
+{
+    fn call(&self, row: &T, _context: &RowContext) {
+        let key = (self.key)(row);
+        match self.routes.get(&key).or(self.default.as_ref()) {
+            Some(action) => action(row),
+            None => log::warn!("No route for key {:?} and no default action, skipping the row", key),
+        }
+    }
+}
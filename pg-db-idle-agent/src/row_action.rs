@@ -0,0 +1,73 @@
+use std::time::SystemTime;
+
+use sqlx::PgPool;
+
+/// Position of a row within the tick that fetched it.
+#[derive(Debug, Clone)]
+pub struct RowContext {
+    /// Zero based index of the row in this tick's result.
+    pub index: usize,
+    /// Number of rows in this tick's result, `None` when rows are actioned before the whole result is known.
+    pub total: Option<usize>,
+    /// Pool for writes following up on the row (e.g. mark-as-sent): the query's `write_pool` if it has one,
+    /// otherwise the pool the row was read from.
+    pub write_pool: PgPool,
+    /// Tick that fetched the row, counting from 1 like `TickReport::tick`.
+    pub tick: u64,
+    /// When the row was fetched.
+    pub observed_at: SystemTime,
+}
+
+/// Per-row action of a query.
+/// Implemented for every `Fn(&T)` closure, wrap a `Fn(&T, &RowContext)` closure in `WithRowContext` to also get
+/// the row's position within the tick (e.g. to log "processing 450/1000"), or a `Fn(&Observed<T>)` closure in
+/// `WithObserved` to get the tick and time it was fetched at.
+/// `start` needs the action to be `Send + Sync`, `start_local` doesn't.
+pub trait RowAction<T>: 'static {
+    fn call(&self, row: &T, context: &RowContext);
+}
+
+impl<T, F> RowAction<T> for F
+where
+    F: Fn(&T) + 'static,
+{
+    fn call(&self, row: &T, _context: &RowContext) {
+        self(row)
+    }
+}
+
+/// Action receiving the `RowContext` of each row next to the row itself.
+pub struct WithRowContext<F>(pub F);
+
+impl<T, F> RowAction<T> for WithRowContext<F>
+where
+    F: Fn(&T, &RowContext) + 'static,
+{
+    fn call(&self, row: &T, context: &RowContext) {
+        (self.0)(row, context)
+    }
+}
+
+/// A row together with when and by which tick it was observed, see `WithObserved`.
+#[derive(Debug)]
+pub struct Observed<'a, T> {
+    pub row: &'a T,
+    pub observed_at: SystemTime,
+    pub tick: u64,
+}
+
+/// Action receiving each row as an `Observed`, e.g. to order rows forwarded downstream by when they were seen.
+pub struct WithObserved<F>(pub F);
+
+impl<T, F> RowAction<T> for WithObserved<F>
+where
+    F: Fn(&Observed<'_, T>) + 'static,
+{
+    fn call(&self, row: &T, context: &RowContext) {
+        (self.0)(&Observed {
+            row,
+            observed_at: context.observed_at,
+            tick: context.tick,
+        })
+    }
+}
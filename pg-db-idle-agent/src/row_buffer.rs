@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+/// Slots for rows that were fetched but not actioned yet, see `PgDbAgentParams::with_max_buffered_rows`.
+pub(crate) struct RowBuffer {
+    max: usize,
+    slots: Arc<Semaphore>,
+}
+
+impl RowBuffer {
+    pub(crate) fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            slots: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    /// Waits until `rows` rows fit. A result larger than the whole buffer takes all of it, so it still gets through.
+    pub(crate) async fn reserve(&self, rows: usize) -> Reservation {
+        let held = rows.min(self.max);
+        if let Ok(permits) = Arc::clone(&self.slots).acquire_many_owned(held as u32).await {
+            permits.forget();
+        }
+        Reservation {
+            slots: Arc::clone(&self.slots),
+            held,
+            remaining: rows,
+        }
+    }
+
+    /// Slots taken, by the rows of runs and by a run still waiting for the rest of its slots.
+    pub(crate) fn occupancy(&self) -> usize {
+        self.max - self.slots.available_permits()
+    }
+}
+
+/// Slots held by a run, given back row by row as they are actioned and all at once when the run is dropped.
+pub(crate) struct Reservation {
+    slots: Arc<Semaphore>,
+    held: usize,
+    remaining: usize,
+}
+
+impl Reservation {
+    pub(crate) fn row_done(&mut self) {
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining < self.held {
+            self.held -= 1;
+            self.slots.add_permits(1);
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.slots.add_permits(self.held);
+    }
+}
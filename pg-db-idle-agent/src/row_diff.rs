@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Mutex, PoisonError},
+};
+
+type Hook<T> = Box<dyn Fn(&T) + Send + Sync>;
+type ChangedHook<T> = Box<dyn Fn(&T, &T) + Send + Sync>;
+
+/// Compares every run's rows with the previous run's by key and reports what was added, changed or removed,
+/// a poor man's CDC stream over a periodic `SELECT`, see `PgDbAgentQueryActionParams::with_diff`.
+///
+/// The query must return the whole set on every run: with `auto_limit` or a `cursor_bind` rows that simply weren't
+/// fetched again are reported as removed. The first run reports every row as added. Rows sharing a key are
+/// compared by the last one of the run.
+pub struct RowDiff<T, K> {
+    key: Box<dyn Fn(&T) -> K + Send + Sync>,
+    on_added: Option<Hook<T>>,
+    on_changed: Option<ChangedHook<T>>,
+    on_removed: Option<Hook<T>>,
+    previous: Mutex<HashMap<K, T>>,
+}
+
+impl<T, K> RowDiff<T, K>
+where
+    K: Hash + Eq,
+{
+    pub fn new<D>(key: D) -> Self
+    where
+        D: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        Self {
+            key: Box::new(key),
+            on_added: None,
+            on_changed: None,
+            on_removed: None,
+            previous: Mutex::default(),
+        }
+    }
+
+    /// Called with rows whose key wasn't in the previous run.
+    pub fn with_on_added<H>(mut self, on_added: H) -> Self
+    where
+        H: Fn(&T) + Send + Sync + 'static,
+    {
+        self.on_added = Some(Box::new(on_added));
+        self
+    }
+
+    /// Called with the old and the new row when a key's row isn't equal to the previous run's anymore.
+    pub fn with_on_changed<H>(mut self, on_changed: H) -> Self
+    where
+        H: Fn(&T, &T) + Send + Sync + 'static,
+    {
+        self.on_changed = Some(Box::new(on_changed));
+        self
+    }
+
+    /// Called with the previous run's rows whose key is gone.
+    pub fn with_on_removed<H>(mut self, on_removed: H) -> Self
+    where
+        H: Fn(&T) + Send + Sync + 'static,
+    {
+        self.on_removed = Some(Box::new(on_removed));
+        self
+    }
+}
+
+/// `RowDiff` with its key type erased, so the query params don't need another type parameter.
+pub(crate) trait DiffRows<T>: Send + Sync {
+    fn diff(&self, rows: &[T]);
+}
+
+impl<T, K> DiffRows<T> for RowDiff<T, K>
+where
+    T: PartialEq + Clone + Send,
+    K: Hash + Eq + Send,
+{
+    fn diff(&self, rows: &[T]) {
+        let mut previous = self.previous.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut current = HashMap::with_capacity(rows.len());
+        for row in rows {
+            current.insert((self.key)(row), row.clone());
+        }
+        for (key, row) in &current {
+            match previous.remove(key) {
+                None => {
+                    if let Some(on_added) = &self.on_added {
+                        on_added(row);
+                    }
+                }
+                Some(old) if old != *row => {
+                    if let Some(on_changed) = &self.on_changed {
+                        on_changed(&old, row);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        if let Some(on_removed) = &self.on_removed {
+            previous.values().for_each(on_removed);
+        }
+        *previous = current;
+    }
+}
@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+
+use crate::ActionError;
+
+/// Async per-row handler, the trait counterpart of a closure action.
+///
+/// Implement it on a struct to keep the handler's dependencies (clients, pools, config) as fields and to test
+/// or mock it on its own. Implementations use `#[async_trait]`, closures `Fn(&T) -> Result<(), ActionError>` implement it as is.
+#[async_trait]
+pub trait RowHandler<T>: Send + Sync + 'static
+where
+    T: Sync,
+{
+    async fn handle(&self, row: &T) -> Result<(), ActionError>;
+}
+
+#[async_trait]
+impl<T, F> RowHandler<T> for F
+where
+    T: Sync,
+    F: Fn(&T) -> Result<(), ActionError> + Send + Sync + 'static,
+{
+    async fn handle(&self, row: &T) -> Result<(), ActionError> {
+        self(row)
+    }
+}
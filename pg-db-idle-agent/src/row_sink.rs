@@ -0,0 +1,46 @@
+use async_trait::async_trait;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::SinkError;
+
+/// Async destination rows are forwarded to, e.g. a channel or a Kafka or NATS producer.
+///
+/// The agent sends every row of a tick in order and calls `flush` once the tick's rows were sent, so batching
+/// producers can buffer in `send` and publish in `flush`. Implementations use `#[async_trait]`.
+#[async_trait]
+pub trait RowSink<T>: Send + Sync + 'static
+where
+    T: Send + 'static,
+{
+    async fn send(&self, row: T) -> Result<(), SinkError>;
+
+    async fn flush(&self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Waits for capacity when the channel is full, fails once the receiver was dropped.
+#[async_trait]
+impl<T> RowSink<T> for mpsc::Sender<T>
+where
+    T: Send + 'static,
+{
+    async fn send(&self, row: T) -> Result<(), SinkError> {
+        mpsc::Sender::send(self, row)
+            .await
+            .map_err(|_| SinkError::from("the receiver was dropped"))
+    }
+}
+
+/// Never waits for subscribers, fails when nobody is subscribed.
+#[async_trait]
+impl<T> RowSink<T> for broadcast::Sender<T>
+where
+    T: Send + 'static,
+{
+    async fn send(&self, row: T) -> Result<(), SinkError> {
+        broadcast::Sender::send(self, row)
+            .map(|_| ())
+            .map_err(|_| SinkError::from("no subscribers"))
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+
+/// When the agent ticks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Schedule {
+    /// Tick every `interval_secs` (or the shortest per-query interval).
+    #[default]
+    Interval,
+    /// Tick at the fire times of a cron expression (`sec min hour day-of-month month day-of-week [year]`),
+    /// evaluated in the agent's `cron_timezone`. Every query runs on every fire, per-query intervals are ignored.
+    #[cfg(feature = "cron")]
+    Cron(String),
+}
+
+/// Decides when the agent ticks, for schedules `Schedule` doesn't cover, e.g. adaptive ones or ticks handed out by
+/// an external coordinator, see `PgDbAgentParams::with_scheduler`. The built-in interval is `IntervalScheduler`.
+#[async_trait]
+pub trait Scheduler: Send + Sync {
+    /// Waits until the next tick is due and returns when it fired, `None` stops the agent
+    /// with `StopReason::ScheduleExhausted`.
+    async fn next_tick(&mut self) -> Option<Instant>;
+}
+
+/// Ticks every `period`, the agent's default schedule.
+pub struct IntervalScheduler {
+    interval: Interval,
+    catch_up: CatchUp,
+}
+
+impl IntervalScheduler {
+    /// The first tick fires right away.
+    pub fn new(period: Duration) -> Self {
+        Self::with_catch_up(period, MissedTickBehavior::Burst, None)
+    }
+
+    pub(crate) fn with_catch_up(
+        period: Duration,
+        missed_tick_behavior: MissedTickBehavior,
+        max_catchup_ticks: Option<usize>,
+    ) -> Self {
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(missed_tick_behavior);
+        Self {
+            interval,
+            catch_up: CatchUp {
+                max_ticks: max_catchup_ticks,
+                ticks: 0,
+            },
+        }
+    }
+
+    /// Restarts the interval so the next tick is a full period from now.
+    pub fn reset(&mut self) {
+        self.interval.reset();
+    }
+
+    /// Ticks every `period` from now on, the next tick is a full `period` from now.
+    pub(crate) fn set_period(&mut self, period: Duration) {
+        let missed_tick_behavior = self.interval.missed_tick_behavior();
+        self.interval = time::interval_at(Instant::now() + period, period);
+        self.interval.set_missed_tick_behavior(missed_tick_behavior);
+    }
+}
+
+#[async_trait]
+impl Scheduler for IntervalScheduler {
+    async fn next_tick(&mut self) -> Option<Instant> {
+        let scheduled = self.interval.tick().await;
+        self.catch_up.record(&mut self.interval, scheduled);
+        Some(scheduled)
+    }
+}
+
+/// Ticks at the fire times of a cron expression, see `Schedule::Cron`.
+#[cfg(feature = "cron")]
+struct CronScheduler {
+    schedule: cron::Schedule,
+    timezone: chrono_tz::Tz,
+}
+
+#[cfg(feature = "cron")]
+#[async_trait]
+impl Scheduler for CronScheduler {
+    async fn next_tick(&mut self) -> Option<Instant> {
+        let next = self.schedule.upcoming(self.timezone).next()?;
+        // The fire time as an `Instant`, so the tick reports when it was due rather than when the task woke up.
+        let scheduled = Instant::now()
+            + (next.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .unwrap_or_default();
+        time::sleep_until(scheduled).await;
+        Some(scheduled)
+    }
+}
+
+/// Drives the poll loop for the configured `Schedule` or `Scheduler`.
+pub(crate) enum Ticker {
+    Interval(IntervalScheduler),
+    /// Cron schedules and custom schedulers, which run every query on every tick.
+    Scheduler(Box<dyn Scheduler>),
+}
+
+impl Ticker {
+    pub(crate) fn interval(
+        period: Duration,
+        missed_tick_behavior: MissedTickBehavior,
+        max_catchup_ticks: Option<usize>,
+    ) -> Self {
+        Self::Interval(IntervalScheduler::with_catch_up(period, missed_tick_behavior, max_catchup_ticks))
+    }
+
+    #[cfg(feature = "cron")]
+    pub(crate) fn cron(expression: &str, timezone: chrono_tz::Tz) -> Result<Self, sqlx::Error> {
+        use std::str::FromStr;
+
+        let schedule = cron::Schedule::from_str(expression)
+            .map_err(|e| sqlx::Error::Configuration(Box::new(e)))?;
+        Ok(Self::Scheduler(Box::new(CronScheduler { schedule, timezone })))
+    }
+
+    /// Whether ticks only run the queries whose own interval elapsed.
+    pub(crate) fn honors_query_intervals(&self) -> bool {
+        matches!(self, Self::Interval(_))
+    }
+
+    /// Restarts the interval so the next tick is a full period from now, other schedulers decide on their own.
+    pub(crate) fn reset(&mut self) {
+        if let Self::Interval(interval) = self {
+            interval.reset();
+        }
+    }
+
+    /// Switches the interval to `period`, see `ErrorBackoff`. Other schedulers keep their schedule.
+    pub(crate) fn set_period(&mut self, period: Duration) {
+        if let Self::Interval(interval) = self {
+            interval.set_period(period);
+        }
+    }
+
+    /// Waits for the next tick, `None` once the schedule has no fire times left.
+    pub(crate) async fn tick(&mut self) -> Option<Instant> {
+        match self {
+            Self::Interval(interval) => interval.next_tick().await,
+            Self::Scheduler(scheduler) => scheduler.next_tick().await,
+        }
+    }
+}
+
+/// Caps the ticks `MissedTickBehavior::Burst` fires back-to-back after the loop fell behind.
+struct CatchUp {
+    max_ticks: Option<usize>,
+    ticks: usize,
+}
+
+impl CatchUp {
+    /// Called with every tick as it fires. A tick after which the next one is already due starts or continues
+    /// a burst, once `max_ticks` immediate ticks followed the late one the interval is reset to a full period from now.
+    fn record(&mut self, interval: &mut Interval, scheduled: Instant) {
+        let Some(max_ticks) = self.max_ticks else {
+            return;
+        };
+        if scheduled + interval.period() > Instant::now() {
+            self.ticks = 0;
+        } else if self.ticks >= max_ticks {
+            interval.reset();
+            self.ticks = 0;
+        } else {
+            self.ticks += 1;
+        }
+    }
+}
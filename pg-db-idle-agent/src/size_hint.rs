@@ -0,0 +1,33 @@
+/// Approximate number of bytes a fetched row takes, see `PgDbAgentQueryActionParams::with_size_hint`.
+///
+/// Usually `std::mem::size_of::<Self>()` plus the heap data of the row's variable length fields, which the impls
+/// below cover for the common ones, e.g. `size_of::<Self>() + self.data.heap_size()`.
+pub trait SizeHint {
+    fn size_hint(&self) -> usize;
+
+    /// Bytes owned on the heap only, for summing up the fields of a row.
+    fn heap_size(&self) -> usize {
+        self.size_hint().saturating_sub(std::mem::size_of_val(self))
+    }
+}
+
+impl SizeHint for String {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>() + self.len()
+    }
+}
+
+impl SizeHint for Vec<u8> {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>() + self.len()
+    }
+}
+
+impl<S> SizeHint for Option<S>
+where
+    S: SizeHint,
+{
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>() + self.as_ref().map_or(0, SizeHint::heap_size)
+    }
+}
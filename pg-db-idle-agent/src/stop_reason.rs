@@ -0,0 +1,18 @@
+/// Why the agent's poll loop stopped, passed to the `on_stop` hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// `AgentHandle::shutdown` was called or the future passed to `start_until` resolved.
+    Shutdown,
+    /// `max_consecutive_errors` ticks in a row failed, or one failed with an error that isn't retryable.
+    MaxConsecutiveErrors,
+    /// `max_total_rows` rows were processed.
+    MaxTotalRows,
+    /// `max_ticks` ticks ran.
+    MaxTicks,
+    /// A pool the queries run on was closed, see `PoolClosedPolicy`.
+    PoolClosed,
+    /// The configured schedule could not be parsed, the agent never ticked.
+    InvalidSchedule,
+    /// The schedule has no fire times left (e.g. a cron expression restricted to past years).
+    ScheduleExhausted,
+}
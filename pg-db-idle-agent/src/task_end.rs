@@ -0,0 +1,95 @@
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use futures::FutureExt;
+
+use crate::{agent_handle::AgentShared, AgentSummary, StopReason};
+
+/// How the agent's task ended, passed to the hooks registered with `AgentHandle::on_task_end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskEndReason {
+    /// The poll loop stopped on its own.
+    Stopped(StopReason),
+    /// An action, hook or the loop itself panicked.
+    Panicked,
+    /// The task was aborted, e.g. with `AgentHandle::abort` or by a `shutdown` that timed out.
+    Aborted,
+}
+
+pub(crate) type TaskEndHook = Box<dyn Fn(TaskEndReason) + Send + Sync>;
+
+/// Hooks waiting for the task to end, or how it ended once it did.
+#[derive(Default)]
+pub(crate) struct TaskEnd {
+    /// Totals of the loop, once it stopped on its own.
+    summary: Option<AgentSummary>,
+    ended: Option<TaskEndReason>,
+    hooks: Vec<TaskEndHook>,
+}
+
+impl TaskEnd {
+    /// Runs `hook` once the task ended, or right away if it already has.
+    pub(crate) fn register(task_end: &Mutex<Self>, hook: TaskEndHook) {
+        let mut task_end = task_end.lock().unwrap_or_else(PoisonError::into_inner);
+        match task_end.ended {
+            Some(reason) => {
+                drop(task_end);
+                hook(reason);
+            }
+            None => task_end.hooks.push(hook),
+        }
+    }
+
+    pub(crate) fn record_stop(&mut self, summary: AgentSummary) {
+        self.summary = Some(summary);
+    }
+
+    pub(crate) fn summary(&self) -> Option<AgentSummary> {
+        self.summary
+    }
+}
+
+/// Runs the agent's loop and tells the hooks how its task ended: after the loop stopped, with a panic, or, when the
+/// future is dropped before either, by an abort. The panic is caught to tell it apart and then resumed.
+///
+/// The guard is created before the first poll so that a task aborted before it ever ran still reports it.
+pub(crate) fn supervise<R>(shared: Arc<AgentShared>, run: R) -> impl Future<Output = ()>
+where
+    R: Future<Output = ()>,
+{
+    let mut guard = TaskEndGuard { shared, reason: None };
+    async move {
+        let result = AssertUnwindSafe(run).catch_unwind().await;
+        let summary = guard.shared.task_end.lock().unwrap_or_else(PoisonError::into_inner).summary;
+        guard.reason = match &result {
+            Ok(()) => summary.map(|summary| TaskEndReason::Stopped(summary.stop_reason)),
+            Err(_) => Some(TaskEndReason::Panicked),
+        };
+        drop(guard);
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+struct TaskEndGuard {
+    shared: Arc<AgentShared>,
+    reason: Option<TaskEndReason>,
+}
+
+impl Drop for TaskEndGuard {
+    fn drop(&mut self) {
+        let reason = self.reason.unwrap_or(TaskEndReason::Aborted);
+        // A loop that stopped on its own already did, after a panic or an abort this is the only place left.
+        self.shared.ticks.stop();
+        self.shared.drains.close();
+        let mut task_end = self.shared.task_end.lock().unwrap_or_else(PoisonError::into_inner);
+        task_end.ended = Some(reason);
+        let hooks = std::mem::take(&mut task_end.hooks);
+        drop(task_end);
+        hooks.iter().for_each(|hook| hook(reason));
+    }
+}
@@ -0,0 +1,105 @@
+use std::borrow::Cow;
+
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    trace::{Span, SpanKind, Status, Tracer},
+    KeyValue,
+};
+use sqlx::PgPool;
+
+const TRACER_NAME: &str = "pg-db-idle-agent";
+
+/// Statements longer than this are cut before being attached to the span.
+const MAX_STATEMENT_LEN: usize = 1024;
+
+/// OpenTelemetry span wrapping a single query execution.
+/// The span is started right before the fetch and ended right after it, so its duration is the query duration.
+pub(crate) struct QuerySpan {
+    span: BoxedSpan,
+}
+
+impl QuerySpan {
+    pub(crate) fn start(pool: &PgPool, query: &str, name: Option<&str>) -> Self {
+        let tracer = global::tracer(TRACER_NAME);
+        let span = tracer
+            .span_builder("pg_db_idle_agent.query")
+            .with_kind(SpanKind::Client)
+            .with_attributes(attributes(pool.connect_options().get_database(), query, name))
+            .start(&tracer);
+        Self { span }
+    }
+
+    pub(crate) fn end<R>(mut self, result: &Result<R, sqlx::Error>) {
+        if let Err(e) = result {
+            self.span.set_status(Status::error(e.to_string()));
+        }
+        self.span.end();
+    }
+}
+
+fn attributes(database: Option<&str>, query: &str, name: Option<&str>) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::new("db.system", "postgresql"),
+        KeyValue::new("db.statement", truncate_statement(query).into_owned()),
+    ];
+    if let Some(database) = database {
+        attributes.push(KeyValue::new("db.name", database.to_string()));
+    }
+    if let Some(name) = name {
+        attributes.push(KeyValue::new("pg_db_idle_agent.query.name", name.to_string()));
+    }
+    attributes
+}
+
+fn truncate_statement(query: &str) -> Cow<'_, str> {
+    if query.len() <= MAX_STATEMENT_LEN {
+        return Cow::Borrowed(query);
+    }
+    let mut end = MAX_STATEMENT_LEN;
+    while !query.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!("{}...", &query[..end]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_statement() {
+        let short = "SELECT * FROM example";
+        assert!(matches!(truncate_statement(short), Cow::Borrowed(statement) if statement == short));
+
+        let exact = "x".repeat(MAX_STATEMENT_LEN);
+        assert_eq!(truncate_statement(&exact), exact.as_str());
+
+        // 'é' is two bytes and straddles the cut, it's dropped whole rather than split.
+        let multi_byte = format!("{}é{}", "x".repeat(MAX_STATEMENT_LEN - 1), "x".repeat(10));
+        let truncated = truncate_statement(&multi_byte);
+        assert_eq!(truncated, format!("{}...", "x".repeat(MAX_STATEMENT_LEN - 1)));
+
+        // A four byte character ending right at the cut is kept.
+        let emoji = format!("{}🦀{}", "x".repeat(MAX_STATEMENT_LEN - 4), "x".repeat(10));
+        assert_eq!(truncate_statement(&emoji), format!("{}🦀...", "x".repeat(MAX_STATEMENT_LEN - 4)));
+    }
+
+    #[test]
+    fn test_span_attributes() {
+        let named = attributes(Some("test"), "SELECT * FROM example", Some("examples"));
+        assert_eq!(
+            named,
+            vec![
+                KeyValue::new("db.system", "postgresql"),
+                KeyValue::new("db.statement", "SELECT * FROM example"),
+                KeyValue::new("db.name", "test"),
+                KeyValue::new("pg_db_idle_agent.query.name", "examples"),
+            ]
+        );
+
+        let statement = "x".repeat(MAX_STATEMENT_LEN + 1);
+        let unnamed = attributes(None, &statement, None);
+        assert_eq!(unnamed.len(), 2);
+        assert_eq!(unnamed[1].value.as_str().len(), MAX_STATEMENT_LEN + "...".len());
+    }
+}
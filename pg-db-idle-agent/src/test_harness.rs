@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use sqlx::{postgres::PgRow, PgPool};
+
+use crate::{PgDbAgentParams, PgDbAgentQueryActionParams, PgDbIdleAgent, RowAction};
+
+/// Wires a test's pool into an agent and keeps the rows its actions were called with, e.g. with the isolated
+/// database `#[sqlx::test]` hands every test:
+///
+/// ```ignore
+/// #[sqlx::test(fixtures("jobs"))]
+/// async fn picks_up_pending_jobs(pool: PgPool) {
+///     let harness = AgentTestHarness::<Job>::new(pool);
+///     let params = PgDbAgentParams::new(
+///         vec![harness.query("SELECT * FROM jobs WHERE done = false")],
+///         Duration::from_secs(1),
+///         |e: sqlx::Error| panic!("{e}"),
+///     )
+///     .unwrap();
+///     assert_eq!(harness.run_once(params).await.unwrap().len(), 2);
+/// }
+/// ```
+pub struct AgentTestHarness<T> {
+    pool: PgPool,
+    rows: Arc<Mutex<Vec<T>>>,
+}
+
+impl<T> AgentTestHarness<T>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Clone + Send + Sync + Unpin + 'static,
+{
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            rows: Arc::default(),
+        }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// An action keeping a copy of every row it's called with.
+    pub fn capture(&self) -> impl Fn(&T) + Send + Sync + 'static {
+        let rows = Arc::clone(&self.rows);
+        move |row: &T| rows.lock().unwrap_or_else(PoisonError::into_inner).push(row.clone())
+    }
+
+    /// A query action running `query` on the harness's pool with `capture` as its action.
+    pub fn query(&self, query: impl Into<String>) -> PgDbAgentQueryActionParams<T, impl Fn(&T) + Send + Sync + 'static> {
+        PgDbAgentQueryActionParams::new(self.pool.clone(), query.into(), self.capture())
+    }
+
+    /// Builds the agent from `params` and runs a single tick with `PgDbIdleAgent::run_once`, returning the rows
+    /// captured so far, which are then cleared.
+    pub async fn run_once<F, E>(&self, params: PgDbAgentParams<T, F, E>) -> Result<Vec<T>, sqlx::Error>
+    where
+        F: RowAction<T> + Send + Sync,
+        E: Fn(sqlx::Error) + Send + Sync + 'static,
+    {
+        PgDbIdleAgent::new(params).run_once().await?;
+        Ok(self.take_rows())
+    }
+
+    /// Rows captured since the last `take_rows` or `run_once`, in the order the actions saw them.
+    pub fn take_rows(&self) -> Vec<T> {
+        std::mem::take(&mut *self.rows.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// A tick that finished without errors, returned by `AgentHandle::wait_for_tick`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickReport {
+    /// Number of the tick since `start`, counting failed ticks too.
+    pub tick: u64,
+    /// Rows handed to query actions during the tick.
+    pub rows: u64,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Copy, Default)]
+struct LastTick {
+    report: Option<TickReport>,
+    stopped: bool,
+}
+
+/// Publishes successful ticks to `wait_for_tick` callers.
+pub(crate) struct TickWatch(watch::Sender<LastTick>);
+
+impl Default for TickWatch {
+    fn default() -> Self {
+        Self(watch::Sender::new(LastTick::default()))
+    }
+}
+
+impl TickWatch {
+    pub(crate) fn publish(&self, report: TickReport) {
+        self.0.send_modify(|last_tick| last_tick.report = Some(report));
+    }
+
+    /// Wakes every waiter with `None`, no ticks follow.
+    pub(crate) fn stop(&self) {
+        self.0.send_modify(|last_tick| last_tick.stopped = true);
+    }
+
+    /// The next tick published after this was called, `None` once the loop stopped.
+    pub(crate) async fn next(&self) -> Option<TickReport> {
+        let mut receiver = self.0.subscribe();
+        if receiver.borrow().stopped {
+            return None;
+        }
+        receiver.changed().await.ok()?;
+        let last_tick = *receiver.borrow();
+        if last_tick.stopped {
+            return None;
+        }
+        last_tick.report
+    }
+}
@@ -0,0 +1,26 @@
+use sqlx::{postgres::PgRow, Executor, Postgres};
+
+/// Fetches `query`, which takes no bind values, as `T`s. Unless `persistent` it goes out with the simple query
+/// protocol, since sqlx parses even non-persistent statements as named ones that outlive the transaction,
+/// which breaks behind PgBouncer in transaction mode. Values then arrive as text, which the built-in types decode.
+pub(crate) async fn fetch_all<'c, T, X>(executor: X, query: &str, persistent: bool) -> Result<Vec<T>, sqlx::Error>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Unpin,
+    X: Executor<'c, Database = Postgres>,
+{
+    if persistent {
+        return sqlx::query_as::<_, T>(query).fetch_all(executor).await;
+    }
+    executor.fetch_all(query).await?.iter().map(T::from_row).collect()
+}
+
+/// Like `fetch_all`, but returns the rows as they came.
+pub(crate) async fn fetch_rows<'c, X>(executor: X, query: &str, persistent: bool) -> Result<Vec<PgRow>, sqlx::Error>
+where
+    X: Executor<'c, Database = Postgres>,
+{
+    if persistent {
+        return sqlx::query(query).fetch_all(executor).await;
+    }
+    executor.fetch_all(query).await
+}
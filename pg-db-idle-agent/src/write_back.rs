@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use sqlx::PgPool;
+
+/// Rows a write-back collects before writing, unless `with_write_batch_size` sets another.
+pub const DEFAULT_WRITE_BATCH_SIZE: usize = 100;
+
+pub type WriteErrorHandler = Box<dyn Fn(sqlx::Error) + Send + Sync>;
+
+/// Follow-up write for the rows a query's action ran for, e.g. marking them as sent, see
+/// `PgDbAgentQueryActionParams::with_write_back`.
+///
+/// The ids of actioned rows are collected and written with one `statement` per `write_batch_size` rows, which gets
+/// them as a `BIGINT[]` in `$1`, e.g. `UPDATE outbox SET sent_at = now() WHERE id = ANY($1)`, instead of a commit per
+/// row. A run writes what's left once its rows are done. Rows of a batch that wasn't written yet when the run panicked
+/// or was cancelled aren't written back, so they come back in a later run: actions must tolerate seeing a row twice.
+pub struct WriteBack<T> {
+    pub statement: String,
+    pub id_extractor: Box<dyn Fn(&T) -> i64 + Send + Sync>,
+    pub write_batch_size: usize,
+    pub on_write_error: Option<WriteErrorHandler>,
+}
+
+impl<T> WriteBack<T> {
+    pub fn new<I>(statement: impl Into<String>, id_extractor: I) -> Self
+    where
+        I: Fn(&T) -> i64 + Send + Sync + 'static,
+    {
+        Self {
+            statement: statement.into(),
+            id_extractor: Box::new(id_extractor),
+            write_batch_size: DEFAULT_WRITE_BATCH_SIZE,
+            on_write_error: None,
+        }
+    }
+
+    /// Rows written back together, at least 1 (a write per row).
+    pub fn with_write_batch_size(mut self, write_batch_size: usize) -> Self {
+        self.write_batch_size = write_batch_size.max(1);
+        self
+    }
+
+    /// Called with every failed write, whose rows then come back in a later run. Without it the error is logged.
+    pub fn with_write_error_handler<H>(mut self, on_write_error: H) -> Self
+    where
+        H: Fn(sqlx::Error) + Send + Sync + 'static,
+    {
+        self.on_write_error = Some(Box::new(on_write_error));
+        self
+    }
+}
+
+/// Ids of a run's actioned rows waiting to be written back.
+pub(crate) struct PendingWrites<T> {
+    write_back: Arc<WriteBack<T>>,
+    pool: PgPool,
+    persistent: bool,
+    ids: Vec<i64>,
+}
+
+impl<T> PendingWrites<T> {
+    pub(crate) fn new(write_back: Arc<WriteBack<T>>, pool: PgPool, persistent: bool) -> Self {
+        Self {
+            ids: Vec::with_capacity(write_back.write_batch_size),
+            write_back,
+            pool,
+            persistent,
+        }
+    }
+
+    /// Adds an actioned row, writing the batch once it's full.
+    pub(crate) async fn push(&mut self, row: &T) {
+        self.ids.push((self.write_back.id_extractor)(row));
+        if self.ids.len() >= self.write_back.write_batch_size {
+            self.flush().await;
+        }
+    }
+
+    pub(crate) async fn flush(&mut self) {
+        if self.ids.is_empty() {
+            return;
+        }
+        let ids = std::mem::take(&mut self.ids);
+        let result = sqlx::query(&self.write_back.statement)
+            .bind(ids)
+            .persistent(self.persistent)
+            .execute(&self.pool)
+            .await;
+        if let Err(e) = result {
+            match &self.write_back.on_write_error {
+                Some(on_write_error) => on_write_error(e),
+                None => log::error!("Write-back failed: {}", e),
+            }
+        }
+    }
+}